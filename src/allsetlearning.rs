@@ -1,9 +1,11 @@
 use crate::common::*;
-use crate::pinyin_type::Pinyin;
+use crate::error::*;
+use crate::pinyin_type::read_pinyin;
 use crate::utils::*;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs::File;
+use std::path::Path;
 
 #[derive(Deserialize)]
 struct AllsetlearningEntry {
@@ -12,14 +14,18 @@ struct AllsetlearningEntry {
     count: u32,
 }
 
-pub fn get() -> impl Iterator<Item = CommonEntry> {
-    let file = File::open("res/allsetlearning_grammar_keywords.json").unwrap();
+pub fn get(path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+    let file = File::open(path).in_file(path)?;
     let reader = std::io::BufReader::new(file);
-    let a: HashMap<String, AllsetlearningEntry> = serde_json::from_reader(reader).unwrap();
-    a.into_iter()
+    let a: HashMap<String, AllsetlearningEntry> = serde_json::from_reader(reader).in_file(path)?;
+    Ok(a.into_iter()
         .map(|(k, v)| {
             let mut we = WordEntry::from_id(k.clone());
-            we.pinyin = v.pinyin.split(',').map(Pinyin::from).collect();
+            we.pinyin = v
+                .pinyin
+                .split(',')
+                .map(|x| read_pinyin(x, report, path, None))
+                .collect();
             let url = url::Url::parse(&format!(
                 r#"https://resources.allsetlearning.com/chinese/grammar/{}"#,
                 penc(&k)
@@ -30,4 +36,5 @@ pub fn get() -> impl Iterator<Item = CommonEntry> {
             we
         })
         .map(CommonEntry::from)
+        .collect())
 }
@@ -1,4 +1,5 @@
 use crate::common::*;
+use crate::error::*;
 use crate::utils::*;
 use serde::Deserialize;
 
@@ -18,7 +19,7 @@ impl From<GraphicsEntry> for WordEntry {
                 .zip(o.medians.into_iter())
                 .map(|(s, m)| Stroke {
                     path: s,
-                    start: m[0],
+                    start: m.first().copied().unwrap_or_default(),
                 })
                 .collect(),
         )];
@@ -50,20 +51,30 @@ impl From<DictionaryEntry> for WordEntry {
 
 use std::fs::File;
 use std::io::{self, BufRead};
+use std::path::Path;
 
-pub fn parse_graphics_zh_hans() -> impl Iterator<Item = CommonEntry> {
-    let file = File::open("res/graphicsZhHans.txt").unwrap();
-    let lines = io::BufReader::new(file).lines().map_while(Result::ok);
-    lines
-        .map(|x| serde_json::from_str::<GraphicsEntry>(&x).unwrap())
-        .map(WordEntry::from)
-        .map(CommonEntry::from)
+/// Reads a file with a json object per line
+fn parse_jsonl<T>(path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>>
+where
+    T: serde::de::DeserializeOwned,
+    WordEntry: From<T>,
+{
+    let file = File::open(path).in_file(path)?;
+    let mut ans = vec![];
+    for (i, line) in io::BufReader::new(file).lines().enumerate() {
+        let line = line.at(path, i as u64 + 1)?;
+        let entry = serde_json::from_str::<T>(&line).at(path, i as u64 + 1);
+        if let Some(entry) = report.check(entry)? {
+            let mut w = WordEntry::from(entry);
+            w.stamp(&Provenance::line(i as u64 + 1));
+            ans.push(w.into());
+        }
+    }
+    Ok(ans)
+}
+pub fn parse_graphics_zh_hans(path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+    parse_jsonl::<GraphicsEntry>(path, report)
 }
-pub fn parse_dictionary_zh_hans() -> impl Iterator<Item = CommonEntry> {
-    let file = File::open("res/dictionaryZhHans.txt").unwrap();
-    let lines = io::BufReader::new(file).lines().map_while(Result::ok);
-    lines
-        .map(|x| serde_json::from_str::<DictionaryEntry>(&x).unwrap())
-        .map(WordEntry::from)
-        .map(CommonEntry::from)
+pub fn parse_dictionary_zh_hans(path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+    parse_jsonl::<DictionaryEntry>(path, report)
 }
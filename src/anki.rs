@@ -1,4 +1,8 @@
 use crate::common::*;
+use crate::error::{Context, Error, Result};
+use crate::manifest::*;
+use crate::pinyin_type::{Pinyin, PinyinPart, Syllable, Tone};
+use crate::reading::sandhi;
 use crate::utils::*;
 use const_format::concatcp;
 use genanki_rs::*;
@@ -12,6 +16,21 @@ const POST_HTML_COMMON: &str = r#"<p style="display:none;">{{sort_field}}</p></s
 const POST_HTML_COMMON_NO_SORTFIELD: &str = r#"</span>"#;
 
 const CSS_COMMON: &str = r#"
+/* colours of the tones, the neutral tone is 5 */
+:root {
+    --tone1: #e30000;
+    --tone2: #02b31c;
+    --tone3: #1510f0;
+    --tone4: #8900bf;
+    --tone5: #777777;
+}
+.nightMode, .night_mode {
+    --tone1: #ff6b6b;
+    --tone2: #5fe36f;
+    --tone3: #7f8cff;
+    --tone4: #d583ff;
+    --tone5: #aaaaaa;
+}
 body {
     font-size: 2em;
 }
@@ -33,6 +52,28 @@ ol {
     border-radius: 5px;
     display: inline-block;
 }
+.pos {
+    font-style: italic;
+}
+.regional {
+    font-weight: normal;
+    font-style: italic;
+}
+.tone1 {
+    color: var(--tone1);
+}
+.tone2 {
+    color: var(--tone2);
+}
+.tone3 {
+    color: var(--tone3);
+}
+.tone4 {
+    color: var(--tone4);
+}
+.tone5 {
+    color: var(--tone5);
+}
 .charvg {
     display: inline-block;
     width: 3em;
@@ -45,11 +86,15 @@ ol {
 
 pub static WORD_MODEL: LazyLock<Model> = LazyLock::new(|| {
     const BACK_COMMON: &str = r#"
-    <h1>{{word}}</h1>
+    <h1>{{word_tones}}</h1>
     <h2>{{pinyin}}</h2>
+    {{#regional}}<h4 class="regional">{{regional}}</h4>{{/regional}}
     <h4>{{traditional}}</h4>
     <hr>
+    {{#pos}}<p class="tc pos">{{pos}}</p>{{/pos}}
     <ol>{{definitions}}</ol>
+    {{#measure_words}}<p class="tc">Measure words: {{measure_words}}</p>{{/measure_words}}
+    {{#related}}<ul>{{related}}</ul>{{/related}}
     <hr>
     <ul>{{examples}}</ul>
     <hr>
@@ -159,6 +204,25 @@ pub static WORD_MODEL: LazyLock<Model> = LazyLock::new(|| {
         const BACK: &str = concatcp!(PRE_HTML_COMMON, BACK_INNER, POST_HTML_COMMON);
         Template::new("zh_word_recalling").qfmt(FRONT).afmt(BACK)
     };
+    // only generated for notes with mw_card set
+    let template_measure_word = {
+        const FRONT_INNER: &str = r#"
+        <h2>Which <b style="color:red;">measure word</b>?</h2>
+        <h1>{{word}}</h1>
+        <h2>{{pinyin}}</h2>
+        {{hint:definitions}}
+        "#;
+        const BACK_INNER: &str = BACK_COMMON;
+        const FRONT: &str = concatcp!(
+            "{{#mw_card}}",
+            PRE_HTML_COMMON,
+            FRONT_INNER,
+            POST_HTML_COMMON,
+            "{{/mw_card}}"
+        );
+        const BACK: &str = concatcp!(PRE_HTML_COMMON, BACK_INNER, POST_HTML_COMMON);
+        Template::new("zh_word_measure_word").qfmt(FRONT).afmt(BACK)
+    };
 
     Model::new_with_options(
         MODEL_ID,
@@ -176,12 +240,23 @@ pub static WORD_MODEL: LazyLock<Model> = LazyLock::new(|| {
             Field::new("audio"),
             Field::new("extra"),
             Field::new("penc"),
+            // fields are only ever appended, so existing notes keep their content
+            // not shown on the cards
+            Field::new("provenance"),
+            Field::new("pos"),
+            Field::new("measure_words"),
+            // not shown either, generates the measure word card when set
+            Field::new("mw_card"),
+            Field::new("related"),
+            Field::new("regional"),
+            Field::new("word_tones"),
         ],
         vec![
             template_meaning,
             template_reading,
             template_writing,
             template_recalling,
+            template_measure_word,
         ],
         Some(CSS_COMMON),
         None,
@@ -221,7 +296,7 @@ fn anki_canvas_contrast(idx: usize) -> String {
     let (r, g, b) = rgb(h, s, v);
     format!("#{:02x}{:02x}{:02x}", r, g, b)
 }
-fn svg_from_strokes(strokes: Vec<Stroke>, i0: usize) -> String {
+fn svg_from_strokes(strokes: &[Stroke], i0: usize) -> String {
     format!(
         r#"<svg class="charvg" viewbox="0 0 1024 1024"><g transform="scale(1, -1) translate(0, -900)">{}</g>{}</svg>"#,
         strokes
@@ -249,17 +324,17 @@ fn svg_from_strokes(strokes: Vec<Stroke>, i0: usize) -> String {
             .fold(String::new(), |acc, e| acc + &e)
     )
 }
-fn html_from_char_writing(w: CharWriting, i0: usize) -> String {
+fn html_from_char_writing(w: &CharWriting, i0: usize) -> String {
     match w {
         CharWriting::Strokes(strokes) => svg_from_strokes(strokes, i0),
         CharWriting::Char(c) => format!(r#"<span class="charvg">{}</span>"#, c),
     }
 }
-fn html_from_writing(w: Vec<CharWriting>) -> String {
+fn html_from_writing(w: &[CharWriting]) -> String {
     let mut cw = Vec::<String>::with_capacity(w.len());
     let mut i0 = 0usize;
-    for c in w.into_iter() {
-        let ns = match &c {
+    for c in w.iter() {
+        let ns = match c {
             CharWriting::Strokes(strokes) => strokes.len(),
             CharWriting::Char(_c) => 0,
         };
@@ -271,86 +346,181 @@ fn html_from_writing(w: Vec<CharWriting>) -> String {
     }
     format!(r#"<p class="tc">{}</p>"#, cw.join(""))
 }
-pub fn word_entry_to_note(we: WordEntry, idx: usize) -> Note {
-    let guid = guid_for(we.id());
+/// What a note is made of, apart from its GUID and sort field
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NoteContent {
+    /// The fields after the sort field, in the order of the model
+    pub fields: Vec<String>,
+    pub tags: Vec<String>,
+}
+impl NoteContent {
+    fn into_note(self, model: &Model, idx: usize, guid: &str) -> Note {
+        let sort_field = format!("{:08}", idx);
+        let fields: Vec<&str> = std::iter::once(sort_field.as_str())
+            .chain(self.fields.iter().map(|x| x.as_str()))
+            .collect();
+        Note::new_with_options(
+            model.clone(),
+            fields,
+            None,
+            Some(self.tags.iter().map(|x| x.as_str()).collect()),
+            Some(guid),
+        )
+        .unwrap()
+    }
+}
+
+/// Content of the note of the entry, what ends up in the deck
+pub fn note_content(entry: &CommonEntry, opts: &PackageOptions) -> NoteContent {
+    match entry {
+        CommonEntry::WordEntry(w) => word_note_content(w, opts),
+        CommonEntry::SyllableEntry(s) => syllable_note_content(s, opts),
+        CommonEntry::GrammarEntry(g) => grammar_note_content(g, opts),
+    }
+}
+
+pub fn word_entry_to_note(we: WordEntry, idx: usize, guid: &str, opts: &PackageOptions) -> Note {
+    word_note_content(&we, opts).into_note(&WORD_MODEL, idx, guid)
+}
+fn word_note_content(we: &WordEntry, opts: &PackageOptions) -> NoteContent {
+    let tags = word_tags(we);
+    let provenance = if opts.provenance {
+        we.provenance()
+            .iter()
+            .map(|x| encode_safe(x).to_string())
+            .collect::<Vec<_>>()
+            .join("<br>")
+    } else {
+        String::new()
+    };
+    let measure_words = we
+        .measure_words
+        .iter()
+        .map(|x| {
+            format!(
+                "{} ({})",
+                encode_safe(&x.simplified),
+                opts.inline_pinyin(&x.pinyin)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mw_card = if opts.measure_word_cards && !measure_words.is_empty() {
+        "y"
+    } else {
+        ""
+    };
+    let related = we
+        .relations
+        .iter()
+        .map(|x| {
+            format!(
+                r#"<li>{} <a href="https://www.dong-chinese.com/wiki/{}">{}</a> {}</li>"#,
+                x.kind.name(),
+                penc(&x.word),
+                encode_safe(&x.word),
+                x.pinyin
+                    .as_ref()
+                    .map(|x| opts.inline_pinyin(x))
+                    .unwrap_or_default()
+            )
+        })
+        .fold(String::new(), |acc, e| acc + &e);
     let extra = we
         .extra
         .iter()
         .map(|x| format!("<li>{}</li>", x))
         .fold(String::new(), |acc, e| acc + &e);
-    Note::new_with_options(
-        WORD_MODEL.clone(),
-        vec![
-            // sort_field
-            &format!("{:08}", idx),
-            // word
-            &encode_safe(&we.id),
-            // english_single
-            &we.hsk_lev
-                .and_then(|hsk| {
-                    if hsk < 7 {
-                        we.simple_english().map(|x| encode_safe(&x).to_string())
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or_default(),
-            // pinyin
-            &we.pinyin
-                .iter()
-                .map(|x| encode_safe(&x.to_string()).to_string())
-                .fold(String::new(), |acc, e| {
-                    let sep = if !acc.is_empty() { ", " } else { "" };
-                    acc + sep + &e
-                }),
-            // definitions
-            &we.definitions
-                .into_iter()
-                .map(|x| {
-                    format!(
-                        "<li><b>{}</b>: {}</li>",
-                        &encode_safe(&x.pinyin.unwrap_or_default().to_string()),
-                        x.english
-                            .iter()
-                            .map(|x| format!(r#"<span class="def">{}</span>"#, encode_safe(x)))
-                            .fold(String::new(), |acc, e| acc + &e)
-                    )
-                })
-                .fold(String::new(), |acc, e| acc + &e),
-            // writing
-            &html_from_writing(we.writing),
-            // traditional
-            &encode_safe(&we.traditional.unwrap_or_default()),
-            // examlpes
-            &we.examples
-                .into_iter()
-                .map(|x| {
-                    format!(
-                        "<li><ruby>{}<rt>{}</rt></ruby><br/>{}</li>",
-                        encode_safe(&x.zh),
-                        encode_safe(&x.py.to_string()),
-                        encode_safe(&x.en)
-                    )
-                })
-                .fold(String::new(), |acc, e| acc + &e),
-            // hsk
-            &we.hsk_lev
-                .map(|x| x.to_string())
-                .unwrap_or(String::from("no")),
-            // audio
-            &we.audio_file
-                .map(|x| format!("[sound:{}]", x.file_name().unwrap().to_str().unwrap()))
-                .unwrap_or_default(),
-            // extra
-            &extra,
-            // penc
-            &penc(&we.id).to_string(),
-        ],
-        None,
-        None,
-        Some(&guid),
-    )
-    .unwrap()
+    let fields = vec![
+        // word
+        encode_safe(&we.id).to_string(),
+        // english_single
+        we.hsk_lev
+            .and_then(|hsk| {
+                if hsk < 7 {
+                    we.simple_english().map(|x| encode_safe(&x).to_string())
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_default(),
+        // pinyin
+        opts.pinyin(&we.pinyin),
+        // definitions
+        we.definitions
+            .iter()
+            .map(|x| {
+                format!(
+                    "<li><b>{}</b>: {}</li>",
+                    x.pinyin
+                        .as_ref()
+                        .map(|x| opts.inline_pinyin(x.written()))
+                        .unwrap_or_default(),
+                    x.english
+                        .iter()
+                        .map(|x| format!(r#"<span class="def">{}</span>"#, encode_safe(x)))
+                        .fold(String::new(), |acc, e| acc + &e)
+                )
+            })
+            .fold(String::new(), |acc, e| acc + &e),
+        // writing
+        html_from_writing(&we.writing),
+        // traditional
+        encode_safe(we.traditional.as_deref().unwrap_or_default()).to_string(),
+        // examlpes
+        we.examples
+            .iter()
+            .map(|x| {
+                let py = opts.reading(x);
+                format!(
+                    "<li><ruby>{}<rt>{}</rt></ruby><br/>{}</li>",
+                    tone_hanzi(&x.zh, &py),
+                    opts.pinyin(&[py]),
+                    encode_safe(&x.en)
+                )
+            })
+            .fold(String::new(), |acc, e| acc + &e),
+        // hsk
+        we.hsk_lev
+            .map(|x| x.to_string())
+            .unwrap_or(String::from("no")),
+        // audio
+        we.audio_file
+            .as_ref()
+            .map(|x| format!("[sound:{}]", x.file_name().unwrap().to_str().unwrap()))
+            .unwrap_or_default(),
+        // extra
+        extra,
+        // penc
+        penc(&we.id).to_string(),
+        // provenance
+        provenance,
+        // pos
+        we.pos
+            .map(|x| x.name().replace('-', " "))
+            .unwrap_or_default(),
+        // measure_words
+        measure_words,
+        // mw_card
+        mw_card.to_owned(),
+        // related
+        related,
+        // regional
+        we.regional
+            .iter()
+            .map(|x| {
+                format!(
+                    "{}: {}",
+                    encode_safe(&x.region),
+                    opts.inline_pinyin(&x.pinyin)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        // word_tones
+        tone_hanzi(&we.id, &we.pinyin.first().cloned().unwrap_or_default()),
+    ];
+    NoteContent { fields, tags }
 }
 
 pub static SYLLABLE_MODEL: LazyLock<Model> = LazyLock::new(|| {
@@ -381,25 +551,27 @@ pub static SYLLABLE_MODEL: LazyLock<Model> = LazyLock::new(|| {
         None,
     )
 });
-pub fn syllable_entry_to_note(se: SyllableEntry, idx: usize) -> Note {
-    let guid = guid_for(se.id());
-    Note::new_with_options(
-        SYLLABLE_MODEL.clone(),
-        vec![
-            // sort_field
-            &format!("{:08}", idx),
+pub fn syllable_entry_to_note(
+    se: SyllableEntry,
+    idx: usize,
+    guid: &str,
+    opts: &PackageOptions,
+) -> Note {
+    syllable_note_content(&se, opts).into_note(&SYLLABLE_MODEL, idx, guid)
+}
+fn syllable_note_content(se: &SyllableEntry, opts: &PackageOptions) -> NoteContent {
+    NoteContent {
+        fields: vec![
             // pinyin
-            &encode_safe(&se.id.to_string()),
-            &format!(
+            opts.pinyin(std::slice::from_ref(&se.id)),
+            // audio
+            format!(
                 "[sound:{}]",
                 se.audio_file.file_name().unwrap().to_str().unwrap()
             ),
         ],
-        None,
-        None,
-        Some(&guid),
-    )
-    .unwrap()
+        tags: vec!["type::syllable".to_owned()],
+    }
 }
 
 pub static GRAMMAR_MODEL: LazyLock<Model> = LazyLock::new(|| {
@@ -440,33 +612,437 @@ pub static GRAMMAR_MODEL: LazyLock<Model> = LazyLock::new(|| {
         None,
     )
 });
-pub fn grammar_entry_to_note(ge: GrammarEntry, idx: usize) -> Note {
-    let guid = guid_for(ge.id());
-    Note::new_with_options(
-        GRAMMAR_MODEL.clone(),
-        vec![
-            // sort_field
-            &format!("{:08}", idx),
+pub fn grammar_entry_to_note(
+    ge: GrammarEntry,
+    idx: usize,
+    guid: &str,
+    opts: &PackageOptions,
+) -> Note {
+    grammar_note_content(&ge, opts).into_note(&GRAMMAR_MODEL, idx, guid)
+}
+fn grammar_note_content(ge: &GrammarEntry, opts: &PackageOptions) -> NoteContent {
+    let (spy, epy) = (opts.reading(&ge.structure), opts.reading(&ge.example));
+    NoteContent {
+        fields: vec![
             // szh
-            &encode_safe(&ge.structure.zh),
+            tone_hanzi(&ge.structure.zh, &spy),
             // sen
-            &encode_safe(&ge.structure.en),
+            encode_safe(&ge.structure.en).to_string(),
             // spy
-            &encode_safe(&ge.structure.py.to_string()),
+            opts.pinyin(&[spy]),
             // ezh
-            &encode_safe(&ge.example.zh),
+            tone_hanzi(&ge.example.zh, &epy),
             // een
-            &encode_safe(&ge.example.en),
+            encode_safe(&ge.example.en).to_string(),
             // epy
-            &encode_safe(&ge.example.py.to_string()),
+            opts.pinyin(&[epy]),
             // hsk
-            &ge.hsk_lev
+            ge.hsk_lev
                 .map(|x| x.to_string())
                 .unwrap_or(String::from("no")),
         ],
-        None,
-        None,
-        Some(&guid),
+        tags: grammar_tags(ge),
+    }
+}
+
+fn hsk_tag(lev: Option<u8>) -> Option<String> {
+    lev.map(|x| format!("hsk::{}", x))
+}
+fn word_tags(we: &WordEntry) -> Vec<String> {
+    let mut tags = vec![if we.id.chars().count() == 1 {
+        "type::character".to_owned()
+    } else {
+        "type::word".to_owned()
+    }];
+    tags.extend(hsk_tag(we.hsk_lev));
+    tags.extend(we.pos.map(|x| format!("pos::{}", x.name())));
+    let sources: std::collections::BTreeSet<&str> = we
+        .origins
+        .values()
+        .flatten()
+        .chain(we.definitions.iter().map(|x| &x.origin))
+        .chain(we.freq.iter().map(|x| &x.origin))
+        .chain(we.examples.iter().map(|x| &x.origin))
+        .map(|x| x.source.as_str())
+        .filter(|x| !x.is_empty())
+        .collect();
+    tags.extend(sources.into_iter().map(|x| format!("source::{}", x)));
+    tags
+}
+fn grammar_tags(ge: &GrammarEntry) -> Vec<String> {
+    let mut tags = vec!["type::grammar".to_owned()];
+    tags.extend(hsk_tag(ge.hsk_lev));
+    if !ge.structure.origin.source.is_empty() {
+        tags.push(format!("source::{}", ge.structure.origin.source));
+    }
+    tags
+}
+
+/// A way to split the deck in subdecks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Split {
+    /// Words, syllables and grammar
+    Type,
+    /// Single characters and longer words, other entries are not split
+    Length,
+    /// HSK level
+    Hsk,
+}
+impl std::str::FromStr for Split {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "type" => Ok(Self::Type),
+            "length" => Ok(Self::Length),
+            "hsk" => Ok(Self::Hsk),
+            _ => Err(Error::parse(format!(
+                "unknown split {}, expected type, length or hsk",
+                s
+            ))),
+        }
+    }
+}
+/// A way to write pinyin
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinyinStyle {
+    /// Tone marks, eg: nǐ hǎo
+    Marks,
+    /// Tone numbers, eg: ni3 hao3
+    Numbers,
+    /// Bopomofo, eg: ㄋㄧˇ ㄏㄠˇ
+    Zhuyin,
+    /// Broad IPA with Chao tone letters, eg: ni˨˩˦ xɑu̯˨˩˦
+    Ipa,
+}
+impl std::str::FromStr for PinyinStyle {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "marks" => Ok(Self::Marks),
+            "numbers" => Ok(Self::Numbers),
+            "zhuyin" => Ok(Self::Zhuyin),
+            "ipa" => Ok(Self::Ipa),
+            _ => Err(Error::parse(format!(
+                "unknown pinyin style {}, expected marks, numbers, zhuyin or ipa",
+                s
+            ))),
+        }
+    }
+}
+impl PinyinStyle {
+    fn syllable(self, s: &Syllable) -> String {
+        match self {
+            PinyinStyle::Marks => s.to_string(),
+            PinyinStyle::Numbers => s.to_numbers(),
+            PinyinStyle::Zhuyin => s.to_zhuyin(),
+            PinyinStyle::Ipa => s.to_ipa(),
+        }
+    }
+    /// Escaped, with each syllable in a span of the class of its tone, eg: tone3
+    pub fn to_html(self, py: &Pinyin) -> String {
+        py.join(|x| match x {
+            PinyinPart::Syllable(s) => tone_span(s.tone, &self.syllable(s)),
+            PinyinPart::Other(s) => encode_safe(s).to_string(),
+        })
+    }
+}
+
+fn tone_span(tone: Tone, text: &str) -> String {
+    format!(
+        r#"<span class="tone{}">{}</span>"#,
+        tone.number(),
+        encode_safe(text)
     )
-    .unwrap()
+}
+
+/// Escaped, with each character in a span of the class of the tone it is read with.
+/// The r of erhua syllables is the 儿 after them, when it is written.
+/// Left uncoloured when the reading does not line up with the characters
+pub fn tone_hanzi(zh: &str, py: &Pinyin) -> String {
+    let chars = zh.chars().filter(|x| is_good_cjk(*x)).count();
+    let syllables: Vec<&Syllable> = py.syllables().collect();
+    let with_er: Vec<Tone> = syllables
+        .iter()
+        .flat_map(|x| std::iter::repeat_n(x.tone, x.written_chars()))
+        .collect();
+    let tones: Vec<Tone> = if with_er.len() == chars {
+        with_er
+    } else if syllables.len() == chars {
+        syllables.iter().map(|x| x.tone).collect()
+    } else {
+        return encode_safe(zh).to_string();
+    };
+    let mut tones = tones.into_iter();
+    zh.chars()
+        .map(|c| match is_good_cjk(c).then(|| tones.next()).flatten() {
+            Some(t) => tone_span(t, &c.to_string()),
+            None => encode_safe(&c.to_string()).to_string(),
+        })
+        .collect()
+}
+
+impl Split {
+    /// Name of the subdeck the entry goes in, `None` if it stays in the parent deck
+    fn subdeck(self, entry: &CommonEntry) -> Option<String> {
+        match (self, entry) {
+            (Split::Type, CommonEntry::WordEntry(_)) => Some("Words".into()),
+            (Split::Type, CommonEntry::SyllableEntry(_)) => Some("Syllables".into()),
+            (Split::Type, CommonEntry::GrammarEntry(_)) => Some("Grammar".into()),
+            (Split::Length, CommonEntry::WordEntry(w)) if w.id.chars().count() == 1 => {
+                Some("Characters".into())
+            }
+            (Split::Length, CommonEntry::WordEntry(_)) => Some("Compounds".into()),
+            (Split::Length, _) => None,
+            (Split::Hsk, e) => {
+                let lev = match e {
+                    CommonEntry::WordEntry(w) => w.hsk_lev,
+                    CommonEntry::GrammarEntry(g) => g.hsk_lev,
+                    CommonEntry::SyllableEntry(_) => return None,
+                };
+                Some(match lev {
+                    Some(7..) => "HSK 7-9".into(),
+                    Some(lev) => format!("HSK {}", lev),
+                    None => "No HSK".into(),
+                })
+            }
+        }
+    }
+}
+
+/// How the package is put together
+#[derive(Clone, Debug)]
+pub struct PackageOptions {
+    pub deck_id: i64,
+    pub deck_name: String,
+    /// Fill the hidden provenance field of words, to trace their data back to the sources
+    pub provenance: bool,
+    /// Subdecks the notes are split in, nested in this order
+    pub split: Vec<Split>,
+    /// Add a "which measure word?" card to the words that have some
+    pub measure_word_cards: bool,
+    /// Show sentences with the tones they are spoken with instead of the citation tones
+    pub spoken_tones: bool,
+    /// How pinyin is written on the cards, one line per style
+    pub pinyin_styles: Vec<PinyinStyle>,
+}
+impl Default for PackageOptions {
+    fn default() -> Self {
+        Self {
+            deck_id: DECK_ID,
+            deck_name: "zh".into(),
+            provenance: false,
+            split: vec![],
+            measure_word_cards: false,
+            spoken_tones: false,
+            pinyin_styles: vec![PinyinStyle::Marks],
+        }
+    }
+}
+impl PackageOptions {
+    /// The readings written in each style with their syllables coloured by tone, separated by
+    /// commas, one line per style
+    fn pinyin(&self, readings: &[Pinyin]) -> String {
+        self.pinyin_styles
+            .iter()
+            .map(|style| {
+                let line: Vec<String> = readings.iter().map(|x| style.to_html(x)).collect();
+                line.join(", ")
+            })
+            .collect::<Vec<_>>()
+            .join("<br>")
+    }
+    /// A reading inside a line of text, written in each style separated by slashes
+    fn inline_pinyin(&self, py: &Pinyin) -> String {
+        self.pinyin_styles
+            .iter()
+            .map(|style| style.to_html(py))
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+    /// The reading of a sentence as shown on the cards
+    fn reading(&self, t: &Triplet) -> Pinyin {
+        if self.spoken_tones {
+            sandhi(&t.zh, &t.py)
+        } else {
+            t.py.clone()
+        }
+    }
+    /// Full name of the deck the entry goes in
+    fn deck_name(&self, entry: &CommonEntry) -> String {
+        std::iter::once(self.deck_name.clone())
+            .chain(self.split.iter().filter_map(|x| x.subdeck(entry)))
+            .collect::<Vec<_>>()
+            .join("::")
+    }
+    /// The id of the root deck is given, the ids of subdecks are derived from their name
+    fn deck_id(&self, name: &str) -> i64 {
+        if name == self.deck_name {
+            self.deck_id
+        } else {
+            let mut h = siphasher::sip::SipHasher::new();
+            std::hash::Hash::hash(&(self.deck_id, name), &mut h);
+            std::hash::Hasher::finish(&h) as i64
+        }
+    }
+}
+
+/// Writes the entries as an anki package.
+/// Notes go in the deck named in `opts`, or in its subdecks nested in the order of
+/// [`PackageOptions::split`], eg: "zh::Words::HSK 1". Every note is tagged with its type
+/// (`type::word`, `type::character`, `type::syllable`, `type::grammar`), its HSK level
+/// (`hsk::3`) and the sources of its data (`source::cedict`), words also with their part of
+/// speech (`pos::verb`).
+/// The GUID and position of each entry come from `manifest`, so it can describe a whole build
+/// while only some of its entries are written
+pub fn write_package(
+    entries: Vec<CommonEntry>,
+    manifest: &Manifest,
+    opts: &PackageOptions,
+    path: &std::path::Path,
+) -> Result<()> {
+    let media: Vec<String> = entries.iter().flat_map(|x| x.media()).collect();
+
+    let by_id: std::collections::HashMap<&EntryId, &ManifestEntry> =
+        manifest.entries.iter().map(|x| (&x.id, x)).collect();
+    let mut guids = std::collections::HashSet::<&str>::new();
+    for me in manifest.entries.iter() {
+        if !guids.insert(&me.guid) {
+            return Err(Error::parse(format!("GUID collision for {}", me.id)));
+        }
+    }
+
+    // the root deck is always there, even if the notes are all in subdecks
+    let mut decks = std::collections::BTreeMap::<String, Deck>::new();
+    decks.insert(
+        opts.deck_name.clone(),
+        Deck::new(opts.deck_id, &opts.deck_name, &opts.deck_name),
+    );
+    for entry in entries {
+        let me = by_id
+            .get(&entry.id())
+            .ok_or_else(|| Error::parse(format!("{} is missing from the manifest", entry.id())))?;
+        let name = opts.deck_name(&entry);
+        let deck = decks
+            .entry(name.clone())
+            .or_insert_with(|| Deck::new(opts.deck_id(&name), &name, &name));
+        deck.add_note(entry.into_note(me.position, &me.guid, opts));
+    }
+
+    let mut package = Package::new(
+        decks.into_values().collect(),
+        media.iter().map(|x| x.as_str()).collect(),
+    )?;
+
+    let writer = std::io::BufWriter::new(create_file(path)?);
+    package.write(writer).in_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pinyin_type::CapPinyin;
+    #[test]
+    fn pinyin_styles_everywhere() {
+        let mut w = WordEntry::from_id("书".into());
+        w.pinyin = vec![Pinyin::from("shu1")];
+        w.definitions.push(Definition {
+            pinyin: Some(CapPinyin::from("Shu1")),
+            english: vec!["book".into()],
+            origin: Provenance::default(),
+        });
+        w.measure_words.push(MeasureWord {
+            simplified: "本".into(),
+            traditional: None,
+            pinyin: Pinyin::from("ben3"),
+        });
+        w.relations.push(Relation {
+            kind: RelationKind::SeeAlso,
+            word: "书本".into(),
+            pinyin: Some(Pinyin::from("shu1 ben3")),
+        });
+        w.regional.push(RegionalReading {
+            region: "Taiwan".into(),
+            pinyin: Pinyin::from("shu1"),
+        });
+        let opts = PackageOptions {
+            pinyin_styles: vec![PinyinStyle::Numbers, PinyinStyle::Zhuyin],
+            ..Default::default()
+        };
+        let fields = word_note_content(&w, &opts).fields;
+        let shu = r#"<span class="tone1">shu1</span> / <span class="tone1">ㄕㄨ</span>"#;
+        // definitions
+        assert!(
+            fields[3].contains(&shu.replace("shu1", "Shu1")),
+            "{}",
+            fields[3]
+        );
+        // measure_words
+        assert!(fields[13].contains(r#"<span class="tone3">ben3</span>"#));
+        assert!(fields[13].contains(r#"<span class="tone3">ㄅㄣˇ</span>"#));
+        // related
+        assert!(fields[15].contains(r#"<span class="tone3">ben3</span>"#));
+        // regional
+        assert_eq!(fields[16], format!("Taiwan: {}", shu));
+    }
+    #[test]
+    fn decks_and_tags() {
+        let mut hao = WordEntry::from_id("好".into());
+        hao.hsk_lev = Some(1);
+        hao.pos = Some(PartOfSpeech::Adjective);
+        hao.definitions.push(Definition {
+            pinyin: None,
+            english: vec!["good".into()],
+            origin: Provenance::source("cedict"),
+        });
+        let hao: CommonEntry = hao.into();
+        let women: CommonEntry = WordEntry::from_id("我们".into()).into();
+        let opts = PackageOptions {
+            split: vec![Split::Type, Split::Hsk],
+            ..Default::default()
+        };
+        assert_eq!(opts.deck_name(&hao), "zh::Words::HSK 1");
+        assert_eq!(opts.deck_name(&women), "zh::Words::No HSK");
+        assert_eq!(
+            note_content(&hao, &opts).tags,
+            vec![
+                "type::character",
+                "hsk::1",
+                "pos::adjective",
+                "source::cedict"
+            ]
+        );
+        assert_eq!(note_content(&women, &opts).tags, vec!["type::word"]);
+        let opts = PackageOptions {
+            split: vec![Split::Length],
+            ..Default::default()
+        };
+        assert_eq!(opts.deck_name(&hao), "zh::Characters");
+        assert_eq!(opts.deck_name(&women), "zh::Compounds");
+    }
+    #[test]
+    fn tone_colours() {
+        let py = Pinyin::from("na3 r5, hao3");
+        assert_eq!(
+            PinyinStyle::Numbers.to_html(&py),
+            r#"<span class="tone3">nar3</span>, <span class="tone3">hao3</span>"#
+        );
+        assert_eq!(
+            tone_hanzi("哪儿，好", &py),
+            r#"<span class="tone3">哪</span><span class="tone3">儿</span>，<span class="tone3">好</span>"#
+        );
+        assert_eq!(tone_hanzi("你<好>", &Pinyin::from("ni3")), "你&lt;好&gt;");
+
+        let mut w = WordEntry::from_id("好".into());
+        w.definitions.push(Definition {
+            pinyin: Some(CapPinyin::from("hao4")),
+            english: vec!["to be fond of".into()],
+            origin: Provenance::default(),
+        });
+        let fields = word_note_content(&w, &PackageOptions::default()).fields;
+        // definitions
+        assert_eq!(
+            fields[3],
+            r#"<li><b><span class="tone4">hào</span></b>: <span class="def">to be fond of</span></li>"#
+        );
+    }
 }
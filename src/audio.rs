@@ -1,6 +1,7 @@
 use crate::common::*;
+use crate::error::*;
 use crate::utils::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Clone)]
 pub struct AudioPath {
@@ -23,51 +24,71 @@ impl From<AudioPath> for SyllableEntry {
     }
 }
 
-pub fn get_syllable_audios() -> impl Iterator<Item = CommonEntry> {
+/// Name of the file without the extension
+fn file_prefix(path: &Path) -> Result<&str> {
+    path.file_prefix()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| Error::parse("bad file name"))
+        .in_file(path)
+}
+
+pub fn get_syllable_audios(dir: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
     let mut ans = vec![];
-    for entry in std::fs::read_dir("res/audio2").unwrap() {
-        let path = entry.unwrap().path();
+    for entry in std::fs::read_dir(dir).in_file(dir)? {
+        let path = entry.in_file(dir)?.path();
         if path.is_file() {
-            let name = path.file_prefix().unwrap().to_str().unwrap();
-            let id = name.split_once('_').unwrap().0.to_string();
-            ans.push(SyllableEntry::from(AudioPath { id, path }));
+            let id = file_prefix(&path).and_then(|name| {
+                name.split_once('_')
+                    .map(|x| x.0.to_string())
+                    .ok_or_else(|| Error::parse("file name should be <pinyin>_<speaker>"))
+                    .in_file(&path)
+            });
+            if let Some(id) = report.check(id)? {
+                ans.push(SyllableEntry::from(AudioPath { id, path }));
+            }
         }
     }
     ans.sort_by_key(|s| guid_for(s.id()));
-    ans.into_iter().map(CommonEntry::from)
+    Ok(ans.into_iter().map(CommonEntry::from).collect())
 }
 
+/// Lower quality alternative to `get_syllable_audios`
 #[allow(unused)]
-pub fn get_syllable_audios_old() -> impl Iterator<Item = CommonEntry> {
-    panic!("Use get_syllable_audios for better quality audio or remove this line");
+pub fn get_syllable_audios_old(dir: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
     let mut ans = vec![];
-    for entry in std::fs::read_dir("res/audio-cmn/64k/syllabs").unwrap() {
-        let path = entry.unwrap().path();
+    for entry in std::fs::read_dir(dir).in_file(dir)? {
+        let path = entry.in_file(dir)?.path();
         if path.is_file() {
-            let name = path.file_prefix().unwrap().to_str().unwrap();
-            if name.contains('_') {
-                continue;
+            if let Some(name) = report.check(file_prefix(&path))? {
+                if name.contains('_') {
+                    continue;
+                }
+                let id = name.chars().skip(4).collect();
+                ans.push(SyllableEntry::from(AudioPath { id, path }));
             }
-            let id = name[4..].to_owned();
-            ans.push(SyllableEntry::from(AudioPath { id, path }));
         }
     }
     ans.sort_by_key(|s| guid_for(s.id()));
-    ans.into_iter().map(CommonEntry::from)
+    Ok(ans.into_iter().map(CommonEntry::from).collect())
 }
 
-pub fn get_word_audios() -> impl Iterator<Item = CommonEntry> {
+pub fn get_word_audios(dir: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
     let mut ans = vec![];
-    for entry in std::fs::read_dir("res/audio-cmn/64k/hsk").unwrap() {
-        let path = entry.unwrap().path();
+    for entry in std::fs::read_dir(dir).in_file(dir)? {
+        let path = entry.in_file(dir)?.path();
         if path.is_file() {
-            let name = path.file_prefix().unwrap().to_str().unwrap();
-            if name.contains('_') {
-                continue;
+            if let Some(name) = report.check(file_prefix(&path))? {
+                if name.contains('_') {
+                    continue;
+                }
+                let id = name.chars().skip(4).collect();
+                ans.push(AudioPath { id, path });
             }
-            let id = name[4..].to_owned();
-            ans.push(AudioPath { id, path });
         }
     }
-    ans.into_iter().map(WordEntry::from).map(CommonEntry::from)
+    Ok(ans
+        .into_iter()
+        .map(WordEntry::from)
+        .map(CommonEntry::from)
+        .collect())
 }
@@ -0,0 +1,223 @@
+//! Checks the quality of the data that ends up in the deck
+use crate::common::*;
+use crate::error::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Problem {
+    /// Some character has no stroke data
+    MissingStrokes,
+    UnicodeNameDefinition,
+    GuessedPinyin,
+    NoDefinition,
+    NoAudio,
+    NoExamples,
+    ConflictingHsk,
+    /// Some words of the pinyin could not be read as pinyin
+    UnreadablePinyin,
+}
+impl Problem {
+    pub const ALL: [Problem; 8] = [
+        Problem::MissingStrokes,
+        Problem::UnicodeNameDefinition,
+        Problem::GuessedPinyin,
+        Problem::NoDefinition,
+        Problem::NoAudio,
+        Problem::NoExamples,
+        Problem::ConflictingHsk,
+        Problem::UnreadablePinyin,
+    ];
+    /// Used in the report, the config and on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Problem::MissingStrokes => "missing-strokes",
+            Problem::UnicodeNameDefinition => "unicode-name-definition",
+            Problem::GuessedPinyin => "guessed-pinyin",
+            Problem::NoDefinition => "no-definition",
+            Problem::NoAudio => "no-audio",
+            Problem::NoExamples => "no-examples",
+            Problem::ConflictingHsk => "conflicting-hsk",
+            Problem::UnreadablePinyin => "unreadable-pinyin",
+        }
+    }
+    pub fn from_name(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|x| x.name() == name)
+            .ok_or_else(|| Error::parse(format!("unknown audit problem: {}", name)))
+    }
+}
+
+/// Problems of a word, other entries are not checked
+pub fn problems(w: &WordEntry) -> Vec<Problem> {
+    let mut v = vec![];
+    if w.is_missing_some_writing() {
+        v.push(Problem::MissingStrokes);
+    }
+    if w.flags.contains(&Flag::UnicodeNameDefinition) {
+        v.push(Problem::UnicodeNameDefinition);
+    }
+    if w.flags.contains(&Flag::GuessedPinyin) {
+        v.push(Problem::GuessedPinyin);
+    }
+    if w.definitions.is_empty() {
+        v.push(Problem::NoDefinition);
+    }
+    if w.audio_file.is_none() {
+        v.push(Problem::NoAudio);
+    }
+    if w.examples.is_empty() {
+        v.push(Problem::NoExamples);
+    }
+    if w.flags
+        .iter()
+        .any(|x| matches!(x, Flag::ConflictingHsk(..)))
+    {
+        v.push(Problem::ConflictingHsk);
+    }
+    if w.pinyin.iter().any(|x| x.has_unreadable()) {
+        v.push(Problem::UnreadablePinyin);
+    }
+    v
+}
+
+/// Entries with each problem, in deck order
+#[derive(Clone, Debug, Default)]
+pub struct Audit {
+    pub words: usize,
+    pub found: BTreeMap<Problem, Vec<EntryId>>,
+}
+impl Audit {
+    pub fn new(entries: &[CommonEntry]) -> Self {
+        let mut audit = Audit::default();
+        for entry in entries {
+            if let CommonEntry::WordEntry(w) = entry {
+                audit.words += 1;
+                for p in problems(w) {
+                    audit.found.entry(p).or_default().push(entry.id());
+                }
+            }
+        }
+        audit
+    }
+    pub fn count(&self, p: Problem) -> usize {
+        self.found.get(&p).map(|x| x.len()).unwrap_or(0)
+    }
+    /// Problems found more often than allowed, with how often they were found
+    pub fn exceeded(&self, thresholds: &BTreeMap<Problem, usize>) -> Vec<(Problem, usize)> {
+        thresholds
+            .iter()
+            .filter(|(p, max)| self.count(**p) > **max)
+            .map(|(p, _)| (*p, self.count(*p)))
+            .collect()
+    }
+    /// Counts of each problem, listing at most `limit` entries for each
+    pub fn to_text(&self, limit: usize) -> String {
+        let mut s = String::new();
+        writeln!(s, "{} words", self.words).unwrap();
+        for p in Problem::ALL {
+            let ids = self.found.get(&p).map(|x| x.as_slice()).unwrap_or_default();
+            writeln!(s, "{}: {}", p.name(), ids.len()).unwrap();
+            if !ids.is_empty() && limit > 0 {
+                let shown: Vec<String> = ids.iter().take(limit).map(|x| x.to_string()).collect();
+                write!(s, "  {}", shown.join(" ")).unwrap();
+                if ids.len() > limit {
+                    write!(s, " and {} more", ids.len() - limit).unwrap();
+                }
+                writeln!(s).unwrap();
+            }
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A word with none of the problems
+    fn good() -> WordEntry {
+        let mut w = WordEntry::from_id("好".into());
+        w.pinyin = vec!["hao3".into()];
+        w.writing = vec![CharWriting::Strokes(vec![])];
+        w.definitions = vec![Definition {
+            pinyin: Some("hao3".into()),
+            english: vec!["good".into()],
+            origin: Provenance::default(),
+        }];
+        w.audio_file = Some("hao3.mp3".into());
+        w.examples = vec![Triplet {
+            zh: "很好".into(),
+            en: "very good".into(),
+            py: "hen3 hao3".into(),
+            origin: Provenance::default(),
+        }];
+        w
+    }
+    fn only(p: Problem, change: impl FnOnce(&mut WordEntry)) {
+        let mut w = good();
+        change(&mut w);
+        assert_eq!(problems(&w), vec![p]);
+    }
+
+    #[test]
+    fn good_word() {
+        assert!(problems(&good()).is_empty());
+    }
+    #[test]
+    fn missing_strokes() {
+        only(Problem::MissingStrokes, |w| {
+            w.writing = vec![CharWriting::Char('好')]
+        });
+    }
+    #[test]
+    fn unicode_name_definition() {
+        only(Problem::UnicodeNameDefinition, |w| {
+            w.flags.push(Flag::UnicodeNameDefinition)
+        });
+    }
+    #[test]
+    fn guessed_pinyin() {
+        only(Problem::GuessedPinyin, |w| {
+            w.flags.push(Flag::GuessedPinyin)
+        });
+    }
+    #[test]
+    fn no_definition() {
+        only(Problem::NoDefinition, |w| w.definitions.clear());
+    }
+    #[test]
+    fn no_audio() {
+        only(Problem::NoAudio, |w| w.audio_file = None);
+    }
+    #[test]
+    fn no_examples() {
+        only(Problem::NoExamples, |w| w.examples.clear());
+    }
+    #[test]
+    fn conflicting_hsk() {
+        only(Problem::ConflictingHsk, |w| {
+            w.flags.push(Flag::ConflictingHsk(1, 2))
+        });
+    }
+    #[test]
+    fn unreadable_pinyin() {
+        only(Problem::UnreadablePinyin, |w| {
+            w.pinyin = vec!["hao3 xx5".into()]
+        });
+    }
+    #[test]
+    fn thresholds() {
+        let mut no_audio = good();
+        no_audio.audio_file = None;
+        let entries: Vec<CommonEntry> =
+            vec![good().into(), no_audio.clone().into(), no_audio.into()];
+        let audit = Audit::new(&entries);
+        assert_eq!(audit.words, 3);
+        assert_eq!(audit.count(Problem::NoAudio), 2);
+        let thresholds = BTreeMap::from([(Problem::NoAudio, 1), (Problem::NoExamples, 0)]);
+        assert_eq!(audit.exceeded(&thresholds), vec![(Problem::NoAudio, 2)]);
+        assert!(audit.to_text(1).contains("no-audio: 2\n  好 and 1 more\n"));
+    }
+}
@@ -0,0 +1,102 @@
+//! Per-source cache of parsed entries.
+//! Each source is stored in its own file, named after the hash of its input files, of
+//! [`CACHE_FORMAT_VERSION`] and of the version of the crate, so changing an input only
+//! invalidates the source reading it.
+use crate::common::*;
+use crate::error::*;
+use crate::process::{read_entries, write_entries};
+use crate::sources::*;
+use core::hash::Hasher;
+use siphasher::sip128::{Hasher128, SipHasher13};
+use std::path::{Path, PathBuf};
+
+/// Version of the layout of cached entries and of the parsing of sources.
+/// Bump it whenever an entry struct or a parser changes, so old caches are not misread; releases
+/// invalidate caches anyway
+pub const CACHE_FORMAT_VERSION: u32 = 4;
+
+fn hash_path(h: &mut SipHasher13, path: &Path, rel: &Path) -> Result<()> {
+    if path.is_dir() {
+        let mut children = vec![];
+        for entry in std::fs::read_dir(path).in_file(path)? {
+            children.push(entry.in_file(path)?.file_name());
+        }
+        children.sort();
+        for child in children {
+            hash_path(h, &path.join(&child), &rel.join(&child))?;
+        }
+    } else {
+        h.write(rel.as_os_str().as_encoded_bytes());
+        h.write(&std::fs::read(path).in_file(path)?);
+    }
+    Ok(())
+}
+
+/// Hash of the contents of a file or of all the files in a directory
+pub fn input_hash(path: &Path) -> Result<u128> {
+    let mut h = SipHasher13::new();
+    h.write_u32(CACHE_FORMAT_VERSION);
+    h.write(env!("CARGO_PKG_VERSION").as_bytes());
+    hash_path(&mut h, path, Path::new(""))?;
+    Ok(h.finish128().as_u128())
+}
+
+pub struct SourceCache {
+    dir: PathBuf,
+}
+impl SourceCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+    fn file_for(&self, source: &dyn DataSource, hash: u128) -> PathBuf {
+        self.dir
+            .join(format!("{}.{:032x}.bin", source.name(), hash))
+    }
+    /// Removes the cached versions of the source
+    fn remove(&self, source: &dyn DataSource) -> Result<()> {
+        if !self.dir.is_dir() {
+            return Ok(());
+        }
+        for entry in std::fs::read_dir(&self.dir).in_file(&self.dir)? {
+            let path = entry.in_file(&self.dir)?.path();
+            let name = path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .and_then(|x| x.strip_suffix(".bin"))
+                .and_then(|x| x.rsplit_once('.'))
+                .map(|x| x.0);
+            if name == Some(source.name()) {
+                std::fs::remove_file(&path).in_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+    /// Reads the entries of the source from the cache if its input did not change,
+    /// otherwise loads them and stores them for next time.
+    /// Sources with bad records are not stored, so they get reported on every run.
+    /// Warnings are only reported when the source is parsed, `audit` finds them in cached entries
+    pub fn load(
+        &self,
+        source: &dyn DataSource,
+        path: &Path,
+        report: &ErrorReport,
+    ) -> Result<Vec<CommonEntry>> {
+        let file = self.file_for(source, input_hash(path)?);
+        if file.is_file() {
+            if let Ok(entries) = read_entries(&file) {
+                return Ok(entries);
+            }
+        }
+        // other sources might be loading at the same time, so skipped records are collected apart
+        let source_report = ErrorReport::new(report.on_error());
+        let res = source.load(path, &source_report);
+        let clean = source_report.skipped().is_empty();
+        report.append(source_report);
+        let entries = res?;
+        if clean {
+            self.remove(source)?;
+            write_entries(&file, &entries)?;
+        }
+        Ok(entries)
+    }
+}
@@ -1,101 +1,325 @@
 use crate::common::*;
+use crate::error::*;
 use crate::pinyin_type::*;
 use regex::Regex;
 use std::io::BufRead;
+use std::path::Path;
 use std::sync::LazyLock;
 
-pub static RERE: LazyLock<[Regex; 8]> = LazyLock::new(|| {
+/// Parts of definitions that are removed, with the kind of reference they make to other words
+pub static RERE: LazyLock<[(Regex, Option<RelationKind>); 8]> = LazyLock::new(|| {
+    use RelationKind::*;
     [
-        Regex::new(r#"used in (?:\S)*\[.*?\](?:\(.*\))?(?: and (?:\S)*\[.*?\](?:\(.*\))?)*"#).unwrap(),
-        Regex::new(r#"old variant of (?:\S)*\[.*?\](?:\(.*\))?"#).unwrap(),
-        Regex::new(r#"erhua variant of (?:\S)*\[.*?\](?:\(.*\))?"#).unwrap(),
-        Regex::new(r#"variant of (?:\S)*\[.*?\](?:\(.*\))?"#).unwrap(),
-        Regex::new(r#"see (?:\S)*\[.*?\](?:\(.*\))?"#).unwrap(),
-        Regex::new(r#"see also (?:\S)*\[.*?\](?:\(.*\))?"#).unwrap(),
-        Regex::new(r#",? ?occurring in.*etc"#).unwrap(),
-        Regex::new(r#"\[.*?\]"#).unwrap(),
+        (
+            Regex::new(r#"used in (?:\S)*\[.*?\](?:\(.*\))?(?: and (?:\S)*\[.*?\](?:\(.*\))?)*"#)
+                .unwrap(),
+            Some(UsedIn),
+        ),
+        (
+            Regex::new(r#"old variant of (?:\S)*\[.*?\](?:\(.*\))?"#).unwrap(),
+            Some(VariantOf),
+        ),
+        (
+            Regex::new(r#"erhua variant of (?:\S)*\[.*?\](?:\(.*\))?"#).unwrap(),
+            Some(VariantOf),
+        ),
+        (
+            Regex::new(r#"variant of (?:\S)*\[.*?\](?:\(.*\))?"#).unwrap(),
+            Some(VariantOf),
+        ),
+        (
+            Regex::new(r#"see (?:\S)*\[.*?\](?:\(.*\))?"#).unwrap(),
+            Some(SeeAlso),
+        ),
+        (
+            Regex::new(r#"see also (?:\S)*\[.*?\](?:\(.*\))?"#).unwrap(),
+            Some(SeeAlso),
+        ),
+        (Regex::new(r#",? ?occurring in.*etc"#).unwrap(), None),
+        (Regex::new(r#"\[.*?\]"#).unwrap(), None),
     ]
 });
+/// A word in a reference, eg: `兒|儿[er2]`
+static REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?:[^\s\[\],|]+\|)?([^\s\[\],|]+)\[(.*?)\]"#).unwrap());
 
-fn simplify_def(ss: &str, blacklist: &[char]) -> Option<String> {
+/// Taiwan pronunciation notes, eg: `(Taiwan pr. [jiao4])`
+static TAIWAN_PR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\(?Taiwan pr\. \[(.*?)\]\)?"#).unwrap());
+/// Senses only used in Taiwan
+const TAIWAN_MARK: &str = "(Tw)";
+
+/// The definition without references to other words, and the references
+fn simplify_def(ss: &str, blacklist: &[char]) -> (Option<String>, Vec<Relation>) {
     let mut ans: String = ss.trim().to_owned();
-    for re in RERE.iter() {
+    let mut relations = vec![];
+    for (re, kind) in RERE.iter() {
+        if let Some(kind) = kind {
+            for m in re.find_iter(&ans) {
+                relations.extend(REFERENCE.captures_iter(m.as_str()).map(|c| Relation {
+                    kind: *kind,
+                    word: c[1].to_owned(),
+                    pinyin: Some(Pinyin::from(&c[2])),
+                }));
+            }
+        }
         ans = re.replace_all(&ans, "").trim().to_owned();
     }
     ans = ans.as_str().replace(blacklist, "〇");
-    let ans = ans.trim_matches(|c: char| c.is_whitespace() || c == ',' || c==';');
+    let ans = ans.trim_matches(|c: char| c.is_whitespace() || c == ',' || c == ';');
     if ans.is_empty() {
-        None
+        (None, relations)
     } else {
-        Some(ans.to_owned())
+        (Some(ans.to_owned()), relations)
     }
 }
 
+/// Parses the classifiers of a `CL:個|个[ge4],本[ben3]` definition, without the `CL:`, one
+/// result per classifier
+fn parse_measure_words(s: &str) -> impl Iterator<Item = Result<MeasureWord>> + '_ {
+    s.split(',').map(|x| {
+        let (chars, py) = x
+            .trim()
+            .strip_suffix(']')
+            .and_then(|x| x.split_once('['))
+            .ok_or_else(|| Error::parse(format!("measure word without pinyin: {}", x)))?;
+        let (traditional, simplified) = match chars.split_once('|') {
+            Some((tr, zh)) => (Some(tr.to_owned()), zh.to_owned()),
+            None => (None, chars.to_owned()),
+        };
+        Ok(MeasureWord {
+            simplified,
+            traditional,
+            pinyin: Pinyin::from(py),
+        })
+    })
+}
+
 #[derive(Debug)]
 pub struct CedictEntry {
     pub simplified: String,
     pub traditional: String,
     pub pinyin: String,
     pub definitions: Vec<String>,
+    pub measure_words: Vec<MeasureWord>,
+    pub relations: Vec<Relation>,
+    pub taiwan_pinyin: Vec<String>,
+    /// All the senses are Taiwanese, the reading is not a mainland one
+    pub taiwan_only: bool,
+    /// Parts of the pinyin that could not be read
+    pub diagnostics: Vec<Diagnostic>,
+    /// Malformed parts of the line that were skipped, eg: a measure word without pinyin
+    pub skipped: Vec<Error>,
 }
-impl From<&str> for CedictEntry {
+impl TryFrom<&str> for CedictEntry {
+    type Error = Error;
     // regex sux
-    fn from(s: &str) -> Self {
-        let (tr, s) = s.split_once(' ').unwrap();
-        let (zh, s) = s.split_once(' ').unwrap();
-        assert!(&s[..1] == "[");
-        let (py, s) = s[1..].split_once(']').unwrap();
-        assert!(&s[..2] == " /");
+    fn try_from(s: &str) -> Result<Self> {
+        let (tr, s) = s
+            .split_once(' ')
+            .ok_or_else(|| Error::parse("missing simplified"))?;
+        let (zh, s) = s
+            .split_once(' ')
+            .ok_or_else(|| Error::parse("missing pinyin"))?;
+        let (py, s) = s
+            .strip_prefix('[')
+            .and_then(|s| s.split_once(']'))
+            .ok_or_else(|| Error::parse("pinyin should be between []"))?;
+        let s = s
+            .strip_prefix(" /")
+            .ok_or_else(|| Error::parse("definitions should start with /"))?;
         let mut d = vec![];
+        let mut measure_words = vec![];
+        let mut relations = vec![];
+        let mut taiwan_pinyin = vec![];
+        let mut taiwan_only = true;
+        let mut skipped = vec![];
 
-        let mut r = &s[2..];
+        let mut r = s;
         while let Some((ds, x)) = r.split_once('/') {
             r = x;
-            if let Some(sd) = simplify_def(ds, &tr.chars().chain(zh.chars()).collect::<Vec<_>>()) {
-                d.push(sd);
+            if let Some(cl) = ds.trim().strip_prefix("CL:") {
+                for mw in parse_measure_words(cl) {
+                    match mw {
+                        Ok(mw) => measure_words.push(mw),
+                        Err(e) => skipped.push(e),
+                    }
+                }
+            } else {
+                taiwan_pinyin.extend(TAIWAN_PR.captures_iter(ds).map(|c| c[1].to_owned()));
+                let ds = TAIWAN_PR.replace_all(ds, "");
+                if !ds.trim().is_empty() && !ds.trim().starts_with(TAIWAN_MARK) {
+                    taiwan_only = false;
+                }
+                let blacklist: Vec<char> = tr.chars().chain(zh.chars()).collect();
+                let (sd, mut rel) = simplify_def(&ds, &blacklist);
+                d.extend(sd);
+                relations.append(&mut rel);
             }
         }
-        Self {
+        // a line with only Taiwan senses is a reading of its own
+        let taiwan_only = taiwan_only && !d.is_empty();
+        if taiwan_only {
+            taiwan_pinyin.push(py.to_owned());
+        }
+        Ok(Self {
             simplified: zh.into(),
             traditional: tr.into(),
             pinyin: py.to_owned(),
             definitions: d,
-        }
+            measure_words,
+            relations,
+            taiwan_pinyin,
+            taiwan_only,
+            diagnostics: parse_pinyin(py).1,
+            skipped,
+        })
     }
 }
 impl From<CedictEntry> for WordEntry {
     fn from(o: CedictEntry) -> Self {
         let mut w = WordEntry::from_id(o.simplified);
         w.traditional = Some(o.traditional);
-        w.pinyin = vec![Pinyin::from(&o.pinyin)];
+        if !o.taiwan_only {
+            w.pinyin = vec![Pinyin::from(&o.pinyin).to_lowercase()];
+        }
+        w.dependencies = o
+            .measure_words
+            .iter()
+            .filter(|x| x.simplified != w.id)
+            .map(|x| EntryId::Word(x.simplified.clone()))
+            .collect();
+        w.measure_words = o.measure_words;
+        w.relations = o.relations;
+        w.regional = o
+            .taiwan_pinyin
+            .iter()
+            .map(|x| RegionalReading {
+                region: "Taiwan".into(),
+                pinyin: Pinyin::from(x).to_lowercase(),
+            })
+            .collect();
         w.definitions = if o.definitions.is_empty() {
             vec![]
         } else {
             vec![Definition {
                 pinyin: Some(CapPinyin::from(o.pinyin)),
                 english: o.definitions,
+                origin: Provenance::default(),
             }]
         };
         w
     }
 }
 
-pub fn get_cedict() -> impl Iterator<Item = CommonEntry> {
-    let file = std::fs::File::open("res/cedict_1_0_ts_utf-8_mdbg.txt").unwrap();
+pub fn get_cedict(path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+    let file = std::fs::File::open(path).in_file(path)?;
 
-    let lines = std::io::BufReader::new(file)
-        .lines()
-        .map_while(Result::ok)
-        .filter(|s| {
-            if let Some(x) = s.trim().chars().next() {
-                x != '#'
-            } else {
-                false
+    let mut ans = vec![];
+    for (i, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line.at(path, i as u64 + 1)?;
+        if line.trim().is_empty() || line.trim().starts_with('#') {
+            continue;
+        }
+        let entry = CedictEntry::try_from(line.as_str()).at(path, i as u64 + 1);
+        if let Some(mut entry) = report.check(entry)? {
+            for e in std::mem::take(&mut entry.skipped) {
+                report.warn(e, path, Some(i as u64 + 1));
             }
-        });
+            for d in entry.diagnostics.iter() {
+                let msg = format!("{} in [{}]", d, entry.pinyin);
+                report.warn(Error::parse(msg), path, Some(i as u64 + 1));
+            }
+            let mut w = WordEntry::from(entry);
+            w.stamp(&Provenance::line(i as u64 + 1));
+            ans.push(w.into());
+        }
+    }
+    Ok(ans)
+}
 
-    lines
-        .map(|x| CedictEntry::from(x.as_str()))
-        .map(WordEntry::from)
-        .map(CommonEntry::from)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_line() {
+        let e = CedictEntry::try_from("你好 你好 [ni3 hao3] /hello/hi/").unwrap();
+        assert_eq!(e.simplified, "你好");
+        assert_eq!(e.pinyin, "ni3 hao3");
+        assert_eq!(e.definitions, vec!["hello", "hi"]);
+        assert!(CedictEntry::try_from("你好 你好 ni3 hao3 /hello/").is_err());
+        assert!(CedictEntry::try_from("你好").is_err());
+        let e = CedictEntry::try_from("虾米 蝦米 [xia1 mi3 xx5] /shrimp/").unwrap();
+        assert_eq!(e.diagnostics.len(), 1);
+    }
+    #[test]
+    fn measure_words() {
+        let e =
+            CedictEntry::try_from("書 书 [shu1] /book/CL:本[ben3],冊|册[ce4],部[bu4]/").unwrap();
+        assert_eq!(e.definitions, vec!["book"]);
+        let mw: Vec<_> = e.measure_words.iter().map(|x| &x.simplified).collect();
+        assert_eq!(mw, vec!["本", "册", "部"]);
+        assert_eq!(e.measure_words[1].traditional.as_deref(), Some("冊"));
+        let e = CedictEntry::try_from("書 书 [shu1] /book/CL:本,部[bu4]/").unwrap();
+        let mw: Vec<_> = e.measure_words.iter().map(|x| &x.simplified).collect();
+        assert_eq!(mw, vec!["部"]);
+        assert_eq!(e.skipped.len(), 1);
+        assert_eq!(e.definitions, vec!["book"]);
+    }
+    #[test]
+    fn relations() {
+        let e = CedictEntry::try_from(
+            "兒 儿 [r5] /erhua variant of 兒|儿[er2]/see also 兒化|儿化[er2 hua4]/",
+        )
+        .unwrap();
+        assert!(e.definitions.is_empty());
+        let rel: Vec<_> = e
+            .relations
+            .iter()
+            .map(|x| (x.kind, x.word.as_str()))
+            .collect();
+        assert_eq!(
+            rel,
+            vec![
+                (RelationKind::VariantOf, "儿"),
+                (RelationKind::SeeAlso, "儿化")
+            ]
+        );
+        let e = CedictEntry::try_from(
+            "叮 叮 [ding1] /to sting/used in 叮噹|叮当[ding1 dang1] and 叮嚀|叮咛[ding1 ning2]/",
+        )
+        .unwrap();
+        assert_eq!(e.definitions, vec!["to sting"]);
+        let rel: Vec<_> = e.relations.iter().map(|x| x.word.as_str()).collect();
+        assert_eq!(rel, vec!["叮当", "叮咛"]);
+    }
+    #[test]
+    fn taiwan() {
+        let e =
+            CedictEntry::try_from("垃圾 垃圾 [la1 ji1] /trash/(Taiwan pr. [le4 se4])/").unwrap();
+        assert_eq!(e.definitions, vec!["trash"]);
+        assert_eq!(e.taiwan_pinyin, vec!["le4 se4"]);
+        let e = CedictEntry::try_from("和 和 [han4] /(Tw) and/").unwrap();
+        assert_eq!(e.taiwan_pinyin, vec!["han4"]);
+        let e = CedictEntry::try_from("和 和 [he2] /and/(Tw) together with/").unwrap();
+        assert!(e.taiwan_pinyin.is_empty());
+    }
+    #[test]
+    fn taiwan_only_line() {
+        let mut w: CommonEntry =
+            WordEntry::from(CedictEntry::try_from("和 和 [he2] /and/").unwrap()).into();
+        w.merge(WordEntry::from(CedictEntry::try_from("和 和 [han4] /(Tw) and/").unwrap()).into());
+        let CommonEntry::WordEntry(w) = w else {
+            unreachable!()
+        };
+        assert_eq!(w.pinyin, vec![Pinyin::from("he2")]);
+        let regional: Vec<String> = w.regional.iter().map(|x| x.to_string()).collect();
+        assert_eq!(regional, vec!["Taiwan: hàn"]);
+
+        let mut w = WordEntry::from(CedictEntry::try_from("閒 闲 [xian2] /(Tw) idle/").unwrap());
+        assert!(w.pinyin.is_empty());
+        w.fall_back_on_regional();
+        assert_eq!(w.pinyin, vec![Pinyin::from("xian2")]);
+        assert_eq!(w.regional[0].to_string(), "Taiwan: xián");
+    }
 }
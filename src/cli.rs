@@ -0,0 +1,250 @@
+use ankizh::anki::{PackageOptions, PinyinStyle, Split, DECK_ID};
+use ankizh::config::*;
+use ankizh::error::*;
+use ankizh::graph::CyclePolicy;
+use ankizh::process::{ProcessOptions, ReadingProfile};
+use ankizh::sources::*;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Builds an anki deck for studying mandarin chinese")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Build the anki package
+    Build(BuildArgs),
+    /// Print a compact view of the ordered entries, for debugging purposes
+    Inspect(InspectArgs),
+    /// Process the entries and store them, so they can be reused with --from-cache
+    Cache(CacheArgs),
+    /// Explain the priority of an entry and how it got its position in the deck
+    Explain(ExplainArgs),
+    /// List the cycles in the dependencies between entries and how they are broken
+    Cycles(CyclesArgs),
+    /// Compare two builds, given as manifests or cache files
+    Diff(DiffArgs),
+    /// Report entries with missing or guessed data, failing when there are too many
+    Audit(AuditArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ProcessArgs {
+    /// Maximum number of entries in the deck
+    #[arg(long, default_value_t = ProcessOptions::default().max_entries)]
+    pub max_entries: usize,
+    /// Entries with a lower priority are not included
+    #[arg(long, default_value_t = ProcessOptions::default().min_priority)]
+    pub min_priority: f32,
+    /// Data sources to include, overrides the ones enabled in the config
+    #[arg(long, value_delimiter = ',')]
+    pub sources: Vec<String>,
+    /// Config file describing the data sources
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Skip records that cannot be parsed instead of stopping, and report them at the end
+    #[arg(long)]
+    pub skip_bad_records: bool,
+    /// Where parsed sources are cached between runs
+    #[arg(long, default_value = "out/cache")]
+    pub cache_dir: PathBuf,
+    /// Parse all the sources again, without reading or writing the cache
+    #[arg(long)]
+    pub no_cache: bool,
+    /// How entries are prioritised: balanced, exam, reading or conversation.
+    /// Overrides the strategy in the config
+    #[arg(long)]
+    pub scoring: Option<String>,
+    /// Which dependency to drop from each cycle: lowest-priority or source-precedence
+    #[arg(long, default_value_t = CyclePolicy::default())]
+    pub cycle_policy: CyclePolicy,
+    /// Leave out the words that are only names of people or places, unless they are in an HSK
+    /// list
+    #[arg(long)]
+    pub drop_proper_nouns: bool,
+    /// Which readings come first: mainland, or taiwan to prefer Taiwan pronunciations
+    #[arg(long, default_value_t = ReadingProfile::default())]
+    pub readings: ReadingProfile,
+}
+impl ProcessArgs {
+    /// Loads the config file, if any, and applies the overrides given on the command line
+    pub fn config(&self, registry: &SourceRegistry) -> Result<Config> {
+        let mut config = match self.config {
+            Some(ref path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        if !self.sources.is_empty() {
+            let names: Vec<String> = registry
+                .names()
+                .chain(std::iter::once(ankizh::tatoeba::NAME))
+                .map(|x| x.to_owned())
+                .collect();
+            if let Some(name) = self.sources.iter().find(|x| !names.contains(x)) {
+                return Err(Error::parse(format!("unknown source: {}", name)));
+            }
+            for name in names {
+                let enabled = self.sources.contains(&name);
+                config.sources.entry(name).or_default().enabled = Some(enabled);
+            }
+        }
+        config.check(registry)?;
+        Ok(config)
+    }
+    pub fn options(&self, config: &Config) -> Result<ProcessOptions> {
+        Ok(ProcessOptions {
+            max_entries: self.max_entries,
+            min_priority: self.min_priority,
+            cache_dir: (!self.no_cache).then(|| self.cache_dir.clone()),
+            cycle_policy: self.cycle_policy,
+            scorer: Arc::new(config.scorer(self.scoring.as_deref())?),
+            drop_proper_nouns: self.drop_proper_nouns,
+            readings: self.readings,
+        })
+    }
+    pub fn on_error(&self) -> OnError {
+        if self.skip_bad_records {
+            OnError::Skip
+        } else {
+            OnError::Abort
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct EntriesArgs {
+    #[command(flatten)]
+    pub process: ProcessArgs,
+    /// Read the entries from a cache file instead of processing them
+    #[arg(long)]
+    pub from_cache: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct BuildArgs {
+    #[command(flatten)]
+    pub entries: EntriesArgs,
+    /// Where to write the package
+    #[arg(short, long, default_value = "out/test.apkg")]
+    pub output: PathBuf,
+    #[arg(long, default_value = "zh")]
+    pub deck_name: String,
+    #[arg(long, default_value_t = DECK_ID)]
+    pub deck_id: i64,
+    /// Where to write the manifest of the build, next to the package by default
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+    /// Manifest of the previous build, to keep the GUIDs of its notes and report what changed
+    #[arg(long)]
+    pub previous: Option<PathBuf>,
+    /// Only put the new and changed notes in the package
+    #[arg(long, requires = "previous")]
+    pub changes_only: bool,
+    /// Fill the hidden provenance field of words with the sources of their data
+    #[arg(long)]
+    pub provenance: bool,
+    /// Split the deck in subdecks by type (words, syllables, grammar), length (characters,
+    /// compounds) or hsk level, nested in the order given
+    #[arg(long, value_delimiter = ',')]
+    pub split: Vec<Split>,
+    /// Add a "which measure word?" card to the words that have measure words
+    #[arg(long)]
+    pub measure_word_cards: bool,
+    /// Show sentences with the tones they are spoken with (eg: bú shì) instead of the citation
+    /// tones (bù shì)
+    #[arg(long)]
+    pub spoken_tones: bool,
+    /// How pinyin is written on the cards: marks (nǐ), numbers (ni3), zhuyin (ㄋㄧˇ) or ipa,
+    /// several styles are shown one per line
+    #[arg(long, value_delimiter = ',', default_value = "marks")]
+    pub pinyin_style: Vec<PinyinStyle>,
+}
+impl BuildArgs {
+    pub fn package_options(&self) -> PackageOptions {
+        PackageOptions {
+            deck_id: self.deck_id,
+            deck_name: self.deck_name.clone(),
+            provenance: self.provenance,
+            split: self.split.clone(),
+            measure_word_cards: self.measure_word_cards,
+            spoken_tones: self.spoken_tones,
+            pinyin_styles: self.pinyin_style.clone(),
+        }
+    }
+    pub fn manifest_path(&self) -> PathBuf {
+        self.manifest
+            .clone()
+            .unwrap_or_else(|| self.output.with_extension("manifest.json"))
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct InspectArgs {
+    #[command(flatten)]
+    pub entries: EntriesArgs,
+    /// Print the sources each value of the words comes from
+    #[arg(long)]
+    pub provenance: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct CacheArgs {
+    #[command(flatten)]
+    pub process: ProcessArgs,
+    /// Where to write the cache
+    #[arg(short, long, default_value = "out/cache.bin")]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct ExplainArgs {
+    #[command(flatten)]
+    pub process: ProcessArgs,
+    /// Word, syllable or grammar point to explain
+    pub word: String,
+}
+
+#[derive(Args, Debug)]
+pub struct CyclesArgs {
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Manifest (.json) or cache file of the old build
+    pub old: PathBuf,
+    /// Manifest (.json) or cache file of the new build
+    pub new: PathBuf,
+    /// Print the differences as JSON
+    #[arg(long)]
+    pub json: bool,
+    /// Entries that only moved are listed when they moved by at least this many positions
+    #[arg(long, default_value_t = 1)]
+    pub min_shift: usize,
+}
+
+fn parse_threshold(s: &str) -> std::result::Result<(String, usize), String> {
+    let (name, max) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <problem>=<count>, got {}", s))?;
+    let max = max.parse().map_err(|e| format!("{}: {}", max, e))?;
+    Ok((name.to_owned(), max))
+}
+
+#[derive(Args, Debug)]
+pub struct AuditArgs {
+    #[command(flatten)]
+    pub entries: EntriesArgs,
+    /// Fail if more entries than this have the problem, eg: no-audio=100.
+    /// Overrides the thresholds in the config
+    #[arg(long, value_parser = parse_threshold)]
+    pub max: Vec<(String, usize)>,
+    /// How many entries to list for each problem
+    #[arg(long, default_value_t = 20)]
+    pub limit: usize,
+}
@@ -1,13 +1,139 @@
+use crate::anki::PackageOptions;
 use crate::pinyin_type::{CapPinyin, Pinyin};
+use crate::scoring::Score;
 use crate::utils::*;
 use enum_dispatch::enum_dispatch;
 use ordered_float::NotNan;
 use serde::{Deserialize, Serialize};
 
+/// Where a value comes from, to trace bad data back to its source
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Name of the source, filled in when loading
+    pub source: String,
+    /// Record of the source the value was read from, when known (eg: "line 12")
+    pub record: Option<String>,
+}
+impl Provenance {
+    pub fn source<S: Into<String>>(source: S) -> Self {
+        Self {
+            source: source.into(),
+            record: None,
+        }
+    }
+    pub fn record<S: Into<String>>(record: S) -> Self {
+        Self {
+            source: String::new(),
+            record: Some(record.into()),
+        }
+    }
+    /// 1-based
+    pub fn line(line: u64) -> Self {
+        Self::record(format!("line {}", line))
+    }
+    /// Fills in what is missing from `other`
+    pub fn fill(&mut self, other: &Provenance) {
+        if self.source.is_empty() {
+            self.source.clone_from(&other.source);
+        }
+        if self.record.is_none() {
+            self.record.clone_from(&other.record);
+        }
+    }
+}
+impl std::fmt::Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.record {
+            Some(ref record) => write!(f, "{} {}", self.source, record),
+            None => write!(f, "{}", self.source),
+        }
+    }
+}
+
+/// A classifier used to count a word, eg: 本 for 书
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MeasureWord {
+    pub simplified: String,
+    pub traditional: Option<String>,
+    pub pinyin: Pinyin,
+}
+impl std::fmt::Display for MeasureWord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.simplified, self.pinyin)
+    }
+}
+
+/// A reading used in some region only, eg: the Taiwan pronunciation
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionalReading {
+    pub region: String,
+    pub pinyin: Pinyin,
+}
+impl std::fmt::Display for RegionalReading {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.region, self.pinyin)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RelationKind {
+    /// Another way to write the word, eg: an old or erhua variant
+    VariantOf,
+    SeeAlso,
+    /// A word the character is part of
+    UsedIn,
+}
+impl RelationKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            RelationKind::VariantOf => "variant of",
+            RelationKind::SeeAlso => "see also",
+            RelationKind::UsedIn => "used in",
+        }
+    }
+}
+
+/// A reference to another word, eg: CEDICT's "variant of 兒|儿[er2]"
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Relation {
+    pub kind: RelationKind,
+    /// Simplified form of the word referred to
+    pub word: String,
+    pub pinyin: Option<Pinyin>,
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Definition {
     pub pinyin: Option<CapPinyin>,
     pub english: Vec<String>,
+    pub origin: Provenance,
+}
+impl Definition {
+    /// Readings of names are capitalized in CEDICT (eg: Zhang1), other senses can still all be
+    /// names (eg: "surname Zhang")
+    pub fn is_proper_noun(&self) -> bool {
+        self.pinyin.as_ref().is_some_and(|x| x.is_capitalized())
+            || (!self.english.is_empty() && self.english.iter().all(|x| is_proper_noun_sense(x)))
+    }
+}
+
+/// Whether an english sense names someone or somewhere instead of giving a meaning
+pub fn is_proper_noun_sense(sense: &str) -> bool {
+    const PATTERNS: [&str; 8] = [
+        "surname ",
+        "(surname)",
+        "place name",
+        "(place name)",
+        "name of ",
+        "personal name",
+        "given name",
+        "(name)",
+    ];
+    let sense = sense.trim().to_lowercase();
+    // unihan glosses are often a bare "surname"
+    PATTERNS
+        .iter()
+        .any(|x| sense.starts_with(x) || sense == x.trim_end())
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -16,6 +142,22 @@ pub struct Priority {
     pub max: NotNan<f32>,
 }
 
+/// How often a word is used, as a fraction of all the words in a corpus
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Frequency {
+    pub value: NotNan<f32>,
+    /// Scorers can weigh sources differently
+    pub origin: Provenance,
+}
+impl From<NotNan<f32>> for Frequency {
+    fn from(value: NotNan<f32>) -> Self {
+        Self {
+            value,
+            origin: Provenance::default(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Stroke {
     pub path: String,
@@ -27,75 +169,222 @@ pub enum CharWriting {
     Char(char),
 }
 
+/// Something about how an entry was built that is worth checking
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Flag {
+    /// No source had the pinyin, it was guessed from the characters
+    GuessedPinyin,
+    /// No source had a definition, the unicode name of the character is used
+    UnicodeNameDefinition,
+    /// Sources disagree on the HSK level, the lowest one is kept
+    ConflictingHsk(u8, u8),
+    /// No source had a definition, the ones of the word it is a variant of are used
+    VariantDefinition,
+}
+
+/// Dominant part of speech of a word
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum PartOfSpeech {
+    Noun,
+    /// Names of people, places, organisations...
+    ProperNoun,
+    Verb,
+    Adjective,
+    Adverb,
+    Pronoun,
+    Numeral,
+    MeasureWord,
+    Preposition,
+    Conjunction,
+    Particle,
+    Interjection,
+    Onomatopoeia,
+    Time,
+    Locality,
+    /// Idioms and fixed expressions
+    Idiom,
+    Other,
+}
+impl PartOfSpeech {
+    pub const ALL: [PartOfSpeech; 17] = [
+        PartOfSpeech::Noun,
+        PartOfSpeech::ProperNoun,
+        PartOfSpeech::Verb,
+        PartOfSpeech::Adjective,
+        PartOfSpeech::Adverb,
+        PartOfSpeech::Pronoun,
+        PartOfSpeech::Numeral,
+        PartOfSpeech::MeasureWord,
+        PartOfSpeech::Preposition,
+        PartOfSpeech::Conjunction,
+        PartOfSpeech::Particle,
+        PartOfSpeech::Interjection,
+        PartOfSpeech::Onomatopoeia,
+        PartOfSpeech::Time,
+        PartOfSpeech::Locality,
+        PartOfSpeech::Idiom,
+        PartOfSpeech::Other,
+    ];
+    /// From the ICTCLAS tags used by SUBTLEX-CH, eg: "v", "nr", "vn"
+    pub fn from_ictclas(tag: &str) -> Self {
+        if ["nr", "ns", "nt", "nz"].iter().any(|x| tag.starts_with(x)) {
+            return PartOfSpeech::ProperNoun;
+        }
+        match tag.chars().next() {
+            Some('n') => PartOfSpeech::Noun,
+            Some('v') => PartOfSpeech::Verb,
+            Some('a') => PartOfSpeech::Adjective,
+            Some('d') => PartOfSpeech::Adverb,
+            Some('r') => PartOfSpeech::Pronoun,
+            Some('m') => PartOfSpeech::Numeral,
+            Some('q') => PartOfSpeech::MeasureWord,
+            Some('p') => PartOfSpeech::Preposition,
+            Some('c') => PartOfSpeech::Conjunction,
+            Some('u') | Some('y') => PartOfSpeech::Particle,
+            Some('e') => PartOfSpeech::Interjection,
+            Some('o') => PartOfSpeech::Onomatopoeia,
+            Some('t') => PartOfSpeech::Time,
+            Some('f') | Some('s') => PartOfSpeech::Locality,
+            Some('i') | Some('l') => PartOfSpeech::Idiom,
+            _ => PartOfSpeech::Other,
+        }
+    }
+    /// Used in the config and in tags
+    pub fn name(self) -> &'static str {
+        match self {
+            PartOfSpeech::Noun => "noun",
+            PartOfSpeech::ProperNoun => "proper-noun",
+            PartOfSpeech::Verb => "verb",
+            PartOfSpeech::Adjective => "adjective",
+            PartOfSpeech::Adverb => "adverb",
+            PartOfSpeech::Pronoun => "pronoun",
+            PartOfSpeech::Numeral => "numeral",
+            PartOfSpeech::MeasureWord => "measure-word",
+            PartOfSpeech::Preposition => "preposition",
+            PartOfSpeech::Conjunction => "conjunction",
+            PartOfSpeech::Particle => "particle",
+            PartOfSpeech::Interjection => "interjection",
+            PartOfSpeech::Onomatopoeia => "onomatopoeia",
+            PartOfSpeech::Time => "time",
+            PartOfSpeech::Locality => "locality",
+            PartOfSpeech::Idiom => "idiom",
+            PartOfSpeech::Other => "other",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|x| x.name() == name)
+    }
+    /// Whether the sense could be used as this part of speech, CEDICT starts verbs with "to"
+    pub fn fits(self, d: &Definition) -> bool {
+        let proper = d.is_proper_noun();
+        let verb = d.english.iter().any(|x| x.starts_with("to "));
+        match self {
+            PartOfSpeech::ProperNoun => proper,
+            PartOfSpeech::Verb => !proper && verb,
+            _ => !proper && !verb,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WordEntry {
     pub id: String,
     pub pinyin: Vec<Pinyin>,
+    pub regional: Vec<RegionalReading>,
     pub definitions: Vec<Definition>,
     pub simple_definitions: Vec<String>,
-    pub freq: Vec<NotNan<f32>>,
+    pub freq: Vec<Frequency>,
     pub hsk_lev: Option<u8>,
+    pub pos: Option<PartOfSpeech>,
+    pub measure_words: Vec<MeasureWord>,
+    /// Only references to entries of the deck are kept after merging
+    pub relations: Vec<Relation>,
     pub dependencies: Vec<EntryId>,
     pub writing: Vec<CharWriting>,
     pub traditional: Option<String>,
     pub audio_file: Option<std::path::PathBuf>,
     pub examples: Vec<Triplet>,
     pub extra: Vec<String>,
+    /// Filled in after merging, see [`crate::scoring`]
+    pub score: Score,
+    pub flags: Vec<Flag>,
+    /// Sources of the fields whose values do not carry their own provenance, by field name.
+    /// For fields holding a single value the first source is the one it comes from
+    pub origins: std::collections::BTreeMap<String, Vec<Provenance>>,
 }
 impl WordEntry {
     pub fn from_id(id: String) -> Self {
         WordEntry {
             id: id.clone(),
             pinyin: vec![],
+            regional: vec![],
             definitions: vec![],
             simple_definitions: vec![],
             freq: vec![],
             hsk_lev: None,
+            pos: None,
+            measure_words: vec![],
+            relations: vec![],
             dependencies: vec![],
             writing: id.chars().map(CharWriting::Char).collect(),
             traditional: None,
             audio_file: None,
             examples: vec![],
             extra: vec![],
+            score: Score::default(),
+            flags: vec![],
+            origins: Default::default(),
         }
     }
+    /// First sense of the definitions, common senses come before proper nouns and the ones of
+    /// the main reading before the others
     pub fn first_definition(&self) -> Option<String> {
-        let py: CapPinyin = self.pinyin[0].clone().into();
-        self.definitions
-            .iter()
-            .find_map(|x| {
-                if x.pinyin.as_ref() == Some(&py) {
-                    x.english[0]
-                        .as_str()
-                        .split(';')
-                        .next()
-                        .map(|x| x.to_owned())
-                } else {
-                    None
-                }
-            })
-            .or_else(|| {
-                if self.definitions.is_empty() {
-                    None
-                } else {
-                    self.definitions[0].english[0]
-                        .as_str()
-                        .split(';')
-                        .next()
-                        .map(|x| x.to_owned())
-                }
-            })
+        self.first_sense(true)
     }
+    /// Like [`Self::first_definition`], but never a proper noun
+    pub fn first_common_definition(&self) -> Option<String> {
+        self.first_sense(false)
+    }
+    fn first_sense(&self, proper_nouns: bool) -> Option<String> {
+        let py = self.pinyin.first();
+        let mut defs: Vec<&Definition> = self
+            .definitions
+            .iter()
+            .filter(|x| proper_nouns || !x.is_proper_noun())
+            .collect();
+        defs.sort_by_key(|x| {
+            (
+                x.is_proper_noun(),
+                x.pinyin.as_ref().map(|x| x.pinyin()).as_ref() != py,
+            )
+        });
+        defs.into_iter().find_map(|x| {
+            let common = x.english.iter().find(|x| !is_proper_noun_sense(x));
+            common
+                .or(x.english.first().filter(|_| proper_nouns))
+                .and_then(|x| x.split(';').next())
+                .map(|x| x.to_owned())
+        })
+    }
+    /// Words whose definitions all name someone or somewhere
+    pub fn is_only_proper_noun(&self) -> bool {
+        !self.definitions.is_empty() && self.definitions.iter().all(|x| x.is_proper_noun())
+    }
+    /// Prompt of the recalling card, proper nouns are left out since they would give away the
+    /// answer or not be enough to find it
     pub fn simple_english(&self) -> Option<String> {
-        let first = self.first_definition();
-        let defs =
-            first
-                .iter()
-                .chain(self.simple_definitions.iter())
-                .fold(String::new(), |acc, e| {
-                    let sep = if !acc.is_empty() { " | " } else { "" };
-                    acc + sep + e
-                });
+        let first = self.first_common_definition();
+        let defs = first
+            .iter()
+            .chain(
+                self.simple_definitions
+                    .iter()
+                    .filter(|x| !is_proper_noun_sense(x)),
+            )
+            .fold(String::new(), |acc, e| {
+                let sep = if !acc.is_empty() { " | " } else { "" };
+                acc + sep + e
+            });
         let defs = defs.trim();
         if defs.is_empty() {
             None
@@ -104,17 +393,6 @@ impl WordEntry {
             Some(format!("({}) {}", num, defs))
         }
     }
-    pub fn total_priority(&self) -> NotNan<f32> {
-        let freq: NotNan<f32> = self.freq.iter().sum();
-        let hsk_lev = self.hsk_lev.unwrap_or(10);
-
-        let hp = NotNan::new((10 - hsk_lev) as f32 / 10f32).unwrap();
-        let fp = (NotNan::new(freq.log2()).unwrap() + NotNan::new(16f32).unwrap())
-            .max(NotNan::new(0f32).unwrap())
-            / 16f32;
-
-        hp * 0.5 + fp * 0.5
-    }
     fn merge_inner(&mut self, mut o: Self) {
         assert_eq!(self.id, o.id);
         for py in o.pinyin {
@@ -122,6 +400,21 @@ impl WordEntry {
                 self.pinyin.push(py);
             }
         }
+        for r in o.regional {
+            if !self.regional.contains(&r) {
+                self.regional.push(r);
+            }
+        }
+        for mw in o.measure_words {
+            if !self.measure_words.contains(&mw) {
+                self.measure_words.push(mw);
+            }
+        }
+        for r in o.relations {
+            if !self.relations.contains(&r) {
+                self.relations.push(r);
+            }
+        }
         for dp in o.dependencies {
             if !self.dependencies.contains(&dp) {
                 self.dependencies.push(dp);
@@ -132,6 +425,10 @@ impl WordEntry {
         self.freq.append(&mut o.freq);
         self.examples.append(&mut o.examples);
         self.extra.append(&mut o.extra);
+        self.flags.append(&mut o.flags);
+        for (field, mut origins) in o.origins {
+            self.origins.entry(field).or_default().append(&mut origins);
+        }
 
         for (a, b) in self.writing.iter_mut().zip(o.writing.into_iter()) {
             if let CharWriting::Char(_) = a {
@@ -140,14 +437,91 @@ impl WordEntry {
         }
 
         self.traditional = self.traditional.take().or(o.traditional);
+        self.pos = self.pos.take().or(o.pos);
         self.audio_file = self.audio_file.take().or(o.audio_file);
         if let (Some(hska), Some(hskb)) = (self.hsk_lev, o.hsk_lev) {
+            if hska != hskb {
+                self.flags
+                    .push(Flag::ConflictingHsk(hska.min(hskb), hska.max(hskb)));
+            }
             self.hsk_lev = Some(hska.min(hskb));
         } else {
             self.hsk_lev = self.hsk_lev.take().or(o.hsk_lev);
         }
     }
-    fn is_missing_some_writing(&self) -> bool {
+    /// Where each value comes from, one line per field or value
+    pub fn provenance(&self) -> Vec<String> {
+        let join = |v: &[Provenance]| {
+            v.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut lines: Vec<String> = self
+            .origins
+            .iter()
+            .map(|(field, v)| format!("{}: {}", field, join(v)))
+            .collect();
+        for d in self.definitions.iter() {
+            lines.push(format!(
+                "definition {}: {}",
+                d.english.first().map(|x| x.as_str()).unwrap_or_default(),
+                d.origin
+            ));
+        }
+        for f in self.freq.iter() {
+            lines.push(format!("frequency {}: {}", f.value, f.origin));
+        }
+        for e in self.examples.iter() {
+            lines.push(format!("example {}: {}", e.zh, e.origin));
+        }
+        lines
+    }
+    /// Puts the senses matching the part of speech first.
+    /// Unless an HSK list says which reading is studied, the reading of the first matching sense
+    /// becomes the main one
+    pub fn prefer_pos_sense(&mut self) {
+        let Some(pos) = self.pos else {
+            return;
+        };
+        self.definitions.sort_by_key(|d| !pos.fits(d));
+        if self.hsk_lev.is_some() {
+            return;
+        }
+        let Some(py) = self
+            .definitions
+            .first()
+            .filter(|d| pos.fits(d))
+            .and_then(|d| d.pinyin.as_ref())
+        else {
+            return;
+        };
+        if let Some(i) = self.pinyin.iter().position(|x| *x == py.pinyin()) {
+            let main = self.pinyin.remove(i);
+            self.pinyin.insert(0, main);
+        }
+    }
+    /// Makes the first reading of the region the main one
+    pub fn prefer_region(&mut self, region: &str) {
+        let Some(r) = self.regional.iter().find(|x| x.region == region) else {
+            return;
+        };
+        self.pinyin.retain(|x| x != &r.pinyin);
+        self.pinyin.insert(0, r.pinyin.clone());
+    }
+    /// Words only read in some region, eg: with only Taiwan senses in CEDICT, are read the regional
+    /// way, the readings stay in [`WordEntry::regional`]
+    pub fn fall_back_on_regional(&mut self) {
+        if !self.pinyin.is_empty() {
+            return;
+        }
+        for r in self.regional.iter() {
+            if !self.pinyin.contains(&r.pinyin) {
+                self.pinyin.push(r.pinyin.clone());
+            }
+        }
+    }
+    pub fn is_missing_some_writing(&self) -> bool {
         self.writing.len() != self.id.chars().count()
             || self
                 .writing
@@ -157,10 +531,10 @@ impl WordEntry {
 }
 impl Entry for WordEntry {
     fn priority(&self) -> NotNan<f32> {
-        self.total_priority()
+        self.score.total()
     }
-    fn into_note(self, idx: usize) -> genanki_rs::Note {
-        crate::anki::word_entry_to_note(self, idx)
+    fn into_note(self, idx: usize, guid: &str, opts: &PackageOptions) -> genanki_rs::Note {
+        crate::anki::word_entry_to_note(self, idx, guid, opts)
     }
     fn id(&self) -> EntryId {
         EntryId::Word(self.id.clone())
@@ -200,6 +574,47 @@ impl Entry for WordEntry {
             .map(|x| x.as_os_str().to_str().unwrap().to_owned())
             .collect()
     }
+    fn stamp(&mut self, origin: &Provenance) {
+        for d in self.definitions.iter_mut() {
+            d.origin.fill(origin);
+        }
+        for f in self.freq.iter_mut() {
+            f.origin.fill(origin);
+        }
+        for e in self.examples.iter_mut() {
+            e.origin.fill(origin);
+        }
+        let fields = [
+            ("pinyin", !self.pinyin.is_empty()),
+            ("regional", !self.regional.is_empty()),
+            ("simple_definitions", !self.simple_definitions.is_empty()),
+            ("hsk_lev", self.hsk_lev.is_some()),
+            ("pos", self.pos.is_some()),
+            ("measure_words", !self.measure_words.is_empty()),
+            ("relations", !self.relations.is_empty()),
+            ("dependencies", !self.dependencies.is_empty()),
+            (
+                "writing",
+                self.writing
+                    .iter()
+                    .any(|x| matches!(x, CharWriting::Strokes(_))),
+            ),
+            ("traditional", self.traditional.is_some()),
+            ("audio_file", self.audio_file.is_some()),
+            ("extra", !self.extra.is_empty()),
+        ];
+        for (field, is_set) in fields {
+            if is_set {
+                let v = self.origins.entry(field.to_owned()).or_default();
+                if v.is_empty() {
+                    v.push(origin.clone());
+                }
+                for o in v.iter_mut() {
+                    o.fill(origin);
+                }
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -212,8 +627,8 @@ impl Entry for SyllableEntry {
     fn priority(&self) -> NotNan<f32> {
         NotNan::new(10f32).unwrap()
     }
-    fn into_note(self, idx: usize) -> genanki_rs::Note {
-        crate::anki::syllable_entry_to_note(self, idx)
+    fn into_note(self, idx: usize, guid: &str, opts: &PackageOptions) -> genanki_rs::Note {
+        crate::anki::syllable_entry_to_note(self, idx, guid, opts)
     }
     fn id(&self) -> EntryId {
         EntryId::Syllable(self.id.to_string())
@@ -233,6 +648,7 @@ impl Entry for SyllableEntry {
     fn media(&self) -> Vec<String> {
         vec![self.audio_file.as_os_str().to_str().unwrap().to_owned()]
     }
+    fn stamp(&mut self, _origin: &Provenance) {}
 }
 
 use std::sync::LazyLock;
@@ -242,7 +658,9 @@ pub static JIEBA: LazyLock<jieba_rs::Jieba> = LazyLock::new(jieba_rs::Jieba::new
 pub struct Triplet {
     pub zh: String,
     pub en: String,
+    /// Empty when the source has no reading, it is generated by [`crate::reading::Reader`]
     pub py: Pinyin,
+    pub origin: Provenance,
 }
 impl Triplet {
     fn dependencies(&self) -> Vec<EntryId> {
@@ -261,16 +679,15 @@ pub struct GrammarEntry {
     pub hsk_lev: Option<u8>,
     /// 0 for first entry, 1 for last entry
     pub hsk_sublev: Option<f32>,
+    /// Filled in after merging, see [`crate::scoring`]
+    pub score: Score,
 }
 impl Entry for GrammarEntry {
     fn priority(&self) -> NotNan<f32> {
-        let hsk_lev = self.hsk_lev.unwrap_or(10);
-        let hp = NotNan::new((10 - hsk_lev) as f32 / 10f32).unwrap();
-        let fp = 1f32 - self.hsk_sublev.unwrap_or(1f32);
-        hp * 0.5 + hp * fp * 0.25
+        self.score.total()
     }
-    fn into_note(self, idx: usize) -> genanki_rs::Note {
-        crate::anki::grammar_entry_to_note(self, idx)
+    fn into_note(self, idx: usize, guid: &str, opts: &PackageOptions) -> genanki_rs::Note {
+        crate::anki::grammar_entry_to_note(self, idx, guid, opts)
     }
     fn id(&self) -> EntryId {
         EntryId::Grammar(self.id.clone())
@@ -292,6 +709,10 @@ impl Entry for GrammarEntry {
     fn media(&self) -> Vec<String> {
         vec![]
     }
+    fn stamp(&mut self, origin: &Provenance) {
+        self.structure.origin.fill(origin);
+        self.example.origin.fill(origin);
+    }
 }
 
 #[allow(clippy::enum_variant_names)]
@@ -303,7 +724,7 @@ pub enum CommonEntry {
     GrammarEntry,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum EntryId {
     Word(String),
     Syllable(String),
@@ -321,11 +742,91 @@ impl std::fmt::Display for EntryId {
 pub trait Entry {
     /// Higher priority means it should come earlier in the deck
     fn priority(&self) -> NotNan<f32>;
-    fn into_note(self, idx: usize) -> genanki_rs::Note;
+    fn into_note(self, idx: usize, guid: &str, opts: &PackageOptions) -> genanki_rs::Note;
     fn id(&self) -> EntryId;
     fn dependencies(&self) -> Vec<EntryId>;
     fn merge(&mut self, o: CommonEntry);
     fn compact_display(&self) -> String;
     fn to_delete(&self) -> bool;
     fn media(&self) -> Vec<String>;
+    /// Fills in the provenance of the values that do not have a complete one yet
+    fn stamp(&mut self, origin: &Provenance);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn simple_english_without_proper_nouns() {
+        let mut w = WordEntry::from_id("鄭".into());
+        w.simple_definitions = vec!["surname".into()];
+        assert_eq!(w.simple_english(), None);
+        w.simple_definitions.push("solemn".into());
+        assert_eq!(w.simple_english().as_deref(), Some("(1) solemn"));
+        assert!(is_proper_noun_sense("Surname Zheng"));
+        assert!(!is_proper_noun_sense("surnames are inherited"));
+    }
+
+    fn def(pinyin: &str, english: &str) -> Definition {
+        Definition {
+            pinyin: Some(CapPinyin::from(pinyin)),
+            english: vec![english.into()],
+            origin: Provenance::default(),
+        }
+    }
+
+    #[test]
+    fn ictclas_tags() {
+        use PartOfSpeech::*;
+        for (tag, pos) in [
+            ("n", Noun),
+            ("nr", ProperNoun),
+            ("ns", ProperNoun),
+            ("nz", ProperNoun),
+            ("v", Verb),
+            ("vn", Verb),
+            ("a", Adjective),
+            ("q", MeasureWord),
+            ("u", Particle),
+            ("y", Particle),
+            ("f", Locality),
+            ("s", Locality),
+            ("i", Idiom),
+            ("l", Idiom),
+            ("x", Other),
+            ("", Other),
+        ] {
+            assert_eq!(PartOfSpeech::from_ictclas(tag), pos, "{}", tag);
+        }
+    }
+
+    #[test]
+    fn fits() {
+        let walk = def("xing2", "to walk");
+        let row = def("hang2", "row");
+        let name = def("Hang2", "surname Hang");
+        assert!(PartOfSpeech::Verb.fits(&walk));
+        assert!(!PartOfSpeech::Verb.fits(&row));
+        assert!(PartOfSpeech::Noun.fits(&row));
+        assert!(!PartOfSpeech::Noun.fits(&walk));
+        assert!(!PartOfSpeech::Noun.fits(&name));
+        assert!(PartOfSpeech::ProperNoun.fits(&name));
+    }
+
+    #[test]
+    fn prefer_pos_sense() {
+        let mut w = WordEntry::from_id("行".into());
+        w.pinyin = vec![Pinyin::from("hang2"), Pinyin::from("xing2")];
+        w.definitions = vec![def("hang2", "row"), def("xing2", "to walk")];
+        w.pos = Some(PartOfSpeech::Verb);
+        let mut hsk = w.clone();
+        w.prefer_pos_sense();
+        assert_eq!(w.definitions[0].english, vec!["to walk"]);
+        assert_eq!(w.pinyin[0], Pinyin::from("xing2"));
+        // the HSK reading stays the main one
+        hsk.hsk_lev = Some(1);
+        hsk.prefer_pos_sense();
+        assert_eq!(hsk.definitions[0].english, vec!["to walk"]);
+        assert_eq!(hsk.pinyin[0], Pinyin::from("hang2"));
+    }
 }
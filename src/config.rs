@@ -0,0 +1,157 @@
+use crate::audit::Problem;
+use crate::common::PartOfSpeech;
+use crate::error::*;
+use crate::scoring::WeightedScorer;
+use crate::sources::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// How a source is configured, anything missing falls back to the source defaults
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    pub enabled: Option<bool>,
+    /// File or directory the source is read from
+    pub path: Option<PathBuf>,
+    /// Multiplies the frequencies this source provides, overrides the weight given by the
+    /// scoring strategy
+    pub weight: Option<f32>,
+    /// Overrides the precedence of the source when merging
+    pub precedence: Option<i32>,
+}
+
+/// How entries are scored, anything missing falls back to the strategy defaults
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScoringConfig {
+    /// One of [`crate::scoring::STRATEGIES`], balanced if missing
+    pub strategy: Option<String>,
+    pub hsk: Option<f32>,
+    pub frequency: Option<f32>,
+    pub grammar: Option<f32>,
+    pub missing_hsk_level: Option<u8>,
+    /// Multiplies the priority of words by their part of speech, see
+    /// [`crate::common::PartOfSpeech::name`]
+    #[serde(default)]
+    pub pos: BTreeMap<String, f32>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub sources: BTreeMap<String, SourceConfig>,
+    #[serde(default)]
+    pub scoring: ScoringConfig,
+    /// Maximum number of entries with each problem, see [`crate::audit::Problem`]
+    #[serde(default)]
+    pub audit: BTreeMap<String, usize>,
+}
+impl Config {
+    /// Reads a toml config file
+    pub fn load(path: &Path) -> Result<Self> {
+        let s = std::fs::read_to_string(path).in_file(path)?;
+        toml::from_str(&s).in_file(path)
+    }
+    /// Fails if the config mentions a source that does not exist
+    pub fn check(&self, registry: &SourceRegistry) -> Result<()> {
+        for name in self.sources.keys() {
+            if name != crate::tatoeba::NAME && registry.get(name).is_none() {
+                return Err(Error::parse(format!("unknown source in config: {}", name)));
+            }
+        }
+        Ok(())
+    }
+    fn get(&self, name: &str) -> SourceConfig {
+        self.sources.get(name).cloned().unwrap_or_default()
+    }
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.get(name).enabled.unwrap_or(true)
+    }
+    pub fn path(&self, source: &dyn DataSource) -> PathBuf {
+        self.path_or(source.name(), source.default_path())
+    }
+    pub fn path_or(&self, name: &str, default: PathBuf) -> PathBuf {
+        self.get(name).path.unwrap_or(default)
+    }
+    pub fn audit_thresholds(&self) -> Result<BTreeMap<Problem, usize>> {
+        self.audit
+            .iter()
+            .map(|(k, v)| Ok((Problem::from_name(k)?, *v)))
+            .collect()
+    }
+    /// The scorer for the configured strategy, or for `strategy` if given
+    pub fn scorer(&self, strategy: Option<&str>) -> Result<WeightedScorer> {
+        let sc = &self.scoring;
+        let strategy = strategy.or(sc.strategy.as_deref()).unwrap_or("balanced");
+        let mut scorer = WeightedScorer::strategy(strategy)?;
+        scorer.hsk = sc.hsk.unwrap_or(scorer.hsk);
+        scorer.frequency = sc.frequency.unwrap_or(scorer.frequency);
+        scorer.grammar = sc.grammar.unwrap_or(scorer.grammar);
+        scorer.missing_hsk_level = sc.missing_hsk_level.unwrap_or(scorer.missing_hsk_level);
+        for (name, factor) in sc.pos.iter() {
+            let pos = PartOfSpeech::from_name(name)
+                .ok_or_else(|| Error::parse(format!("unknown part of speech: {}", name)))?;
+            scorer.pos.insert(pos, *factor);
+        }
+        for (name, source) in self.sources.iter() {
+            if let Some(weight) = source.weight {
+                scorer.sources.insert(name.clone(), weight);
+            }
+        }
+        Ok(scorer)
+    }
+    pub fn precedence(&self, source: &dyn DataSource) -> i32 {
+        self.get(source.name())
+            .precedence
+            .unwrap_or_else(|| source.precedence())
+    }
+    /// Enabled sources, in the order they should be merged
+    pub fn merge_order<'a>(&self, registry: &'a SourceRegistry) -> Vec<&'a dyn DataSource> {
+        let mut v: Vec<_> = registry
+            .iter()
+            .filter(|s| self.is_enabled(s.name()))
+            .collect();
+        v.sort_by_key(|s| std::cmp::Reverse(self.precedence(*s)));
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> SourceRegistry {
+        let mut r = SourceRegistry::empty();
+        for (name, precedence) in [("a", 20), ("b", 10), ("c", 0)] {
+            r.register(FnSource {
+                name,
+                precedence,
+                default_path: "",
+                load: |_, _| Ok(vec![]),
+            });
+        }
+        r
+    }
+
+    fn order(config: &Config, registry: &SourceRegistry) -> Vec<String> {
+        config
+            .merge_order(registry)
+            .into_iter()
+            .map(|x| x.name().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn precedence() {
+        let r = registry();
+        assert_eq!(order(&Config::default(), &r), vec!["a", "b", "c"]);
+        let config: Config =
+            toml::from_str("[sources.c]\nprecedence = 30\n[sources.b]\nenabled = false").unwrap();
+        assert_eq!(order(&config, &r), vec!["c", "a"]);
+        let b = r.get("b").unwrap();
+        assert_eq!(config.precedence(b), 10);
+        assert_eq!(config.precedence(r.get("c").unwrap()), 30);
+    }
+}
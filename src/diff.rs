@@ -0,0 +1,227 @@
+//! Compares two builds entry by entry
+use crate::anki::PackageOptions;
+use crate::common::*;
+use crate::error::*;
+use crate::manifest::*;
+use crate::process::read_entries;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+impl<T: PartialEq + Clone> Change<T> {
+    fn of(old: &T, new: &T) -> Option<Self> {
+        (old != new).then(|| Change {
+            old: old.clone(),
+            new: new.clone(),
+        })
+    }
+}
+
+/// An entry present in only one of the builds
+#[derive(Clone, Debug, Serialize)]
+pub struct Presence {
+    pub id: EntryId,
+    pub position: usize,
+}
+
+/// An entry present in both builds, only what changed is set
+#[derive(Clone, Debug, Serialize)]
+pub struct EntryDiff {
+    pub id: EntryId,
+    pub position: Change<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinyin: Option<Change<Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub definitions: Option<Change<Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hsk_lev: Option<Change<Option<u8>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media: Option<Change<Vec<String>>>,
+}
+impl EntryDiff {
+    fn is_moved_only(&self) -> bool {
+        self.pinyin.is_none()
+            && self.definitions.is_none()
+            && self.hsk_lev.is_none()
+            && self.media.is_none()
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Diff {
+    pub added: Vec<Presence>,
+    pub removed: Vec<Presence>,
+    pub changed: Vec<EntryDiff>,
+}
+
+/// Reads a build, either from its manifest (`.json`) or from cached entries
+pub fn load_build(path: &Path) -> Result<Manifest> {
+    if path.extension().is_some_and(|x| x == "json") {
+        Manifest::load(path)
+    } else {
+        Manifest::new(&read_entries(path)?, None, &PackageOptions::default())
+    }
+}
+
+/// Entries that only moved are left out unless they moved by at least `min_shift` positions
+pub fn diff(old: &Manifest, new: &Manifest, min_shift: usize) -> Diff {
+    let old_by_id: HashMap<&EntryId, &ManifestEntry> =
+        old.entries.iter().map(|x| (&x.id, x)).collect();
+    let new_by_id: HashMap<&EntryId, &ManifestEntry> =
+        new.entries.iter().map(|x| (&x.id, x)).collect();
+    let mut d = Diff::default();
+    for n in new.entries.iter() {
+        let Some(o) = old_by_id.get(&n.id) else {
+            d.added.push(Presence {
+                id: n.id.clone(),
+                position: n.position,
+            });
+            continue;
+        };
+        let (os, ns) = (&o.summary, &n.summary);
+        let ed = EntryDiff {
+            id: n.id.clone(),
+            position: Change {
+                old: o.position,
+                new: n.position,
+            },
+            pinyin: Change::of(&os.pinyin, &ns.pinyin),
+            definitions: Change::of(&os.definitions, &ns.definitions),
+            hsk_lev: Change::of(&os.hsk_lev, &ns.hsk_lev),
+            media: Change::of(&os.media, &ns.media),
+        };
+        if !ed.is_moved_only() || o.position.abs_diff(n.position) >= min_shift.max(1) {
+            d.changed.push(ed);
+        }
+    }
+    d.removed = old
+        .entries
+        .iter()
+        .filter(|x| !new_by_id.contains_key(&x.id))
+        .map(|x| Presence {
+            id: x.id.clone(),
+            position: x.position,
+        })
+        .collect();
+    d
+}
+
+fn write_list_change(s: &mut String, name: &str, c: &Change<Vec<String>>) {
+    writeln!(s, "    {}:", name).unwrap();
+    for x in c.old.iter().filter(|x| !c.new.contains(x)) {
+        writeln!(s, "      - {}", x).unwrap();
+    }
+    for x in c.new.iter().filter(|x| !c.old.contains(x)) {
+        writeln!(s, "      + {}", x).unwrap();
+    }
+}
+
+impl Diff {
+    /// Human readable changelog, positions are 1-based
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        for a in self.added.iter() {
+            writeln!(s, "+ {} #{}", a.id, a.position + 1).unwrap();
+        }
+        for r in self.removed.iter() {
+            writeln!(s, "- {} #{}", r.id, r.position + 1).unwrap();
+        }
+        for c in self.changed.iter() {
+            writeln!(
+                s,
+                "~ {} #{} -> #{}",
+                c.id,
+                c.position.old + 1,
+                c.position.new + 1
+            )
+            .unwrap();
+            if let Some(ref x) = c.pinyin {
+                writeln!(
+                    s,
+                    "    pinyin: {} -> {}",
+                    x.old.join(", "),
+                    x.new.join(", ")
+                )
+                .unwrap();
+            }
+            if let Some(ref x) = c.definitions {
+                write_list_change(&mut s, "definitions", x);
+            }
+            if let Some(ref x) = c.hsk_lev {
+                let lev = |l: Option<u8>| l.map(|x| x.to_string()).unwrap_or("none".into());
+                writeln!(s, "    hsk: {} -> {}", lev(x.old), lev(x.new)).unwrap();
+            }
+            if let Some(ref x) = c.media {
+                write_list_change(&mut s, "media", x);
+            }
+        }
+        writeln!(
+            s,
+            "{} added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )
+        .unwrap();
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(id: &str, pinyin: &str) -> CommonEntry {
+        let mut w = WordEntry::from_id(id.into());
+        w.pinyin = vec![pinyin.into()];
+        w.into()
+    }
+
+    #[test]
+    fn changes() {
+        let old = Manifest::new(
+            &[word("你", "ni3"), word("好", "hao3"), word("人", "ren2")],
+            None,
+            &PackageOptions::default(),
+        )
+        .unwrap();
+        let new = Manifest::new(
+            &[word("你", "ni3"), word("好", "hao4"), word("大", "da4")],
+            None,
+            &PackageOptions::default(),
+        )
+        .unwrap();
+        let d = diff(&old, &new, 1);
+        let id = |x: &str| EntryId::Word(x.into());
+        let ids = |v: &[Presence]| v.iter().map(|x| x.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&d.added), vec![id("大")]);
+        assert_eq!(ids(&d.removed), vec![id("人")]);
+        assert_eq!(d.changed.len(), 1);
+        assert_eq!(d.changed[0].id, id("好"));
+        let pinyin = d.changed[0].pinyin.as_ref().unwrap();
+        assert_eq!(
+            (&pinyin.old[..], &pinyin.new[..]),
+            (&["hǎo".to_owned()][..], &["hào".to_owned()][..])
+        );
+        assert!(d.changed[0].definitions.is_none());
+    }
+
+    #[test]
+    fn manifest_without_summaries() {
+        let m = Manifest::new(&[word("好", "hao3")], None, &PackageOptions::default()).unwrap();
+        let mut json = serde_json::to_value(&m).unwrap();
+        assert!(serde_json::from_value::<Manifest>(json.clone()).is_ok());
+        // written before summaries were added
+        json["entries"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("summary");
+        assert!(serde_json::from_value::<Manifest>(json).is_err());
+    }
+}
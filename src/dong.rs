@@ -1,8 +1,10 @@
 use crate::common::*;
+use crate::error::*;
 use crate::pinyin_type::*;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::io::BufRead;
+use std::path::Path;
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
@@ -44,7 +46,8 @@ impl From<Word> for Triplet {
         Self {
             zh: word.word.clone(),
             en: word.gloss,
-            py: Pinyin::from_hanzi(word.word),
+            py: Pinyin::default(),
+            origin: Provenance::record("top words"),
         }
     }
 }
@@ -146,6 +149,7 @@ impl From<Dong> for WordEntry {
                     .into_iter()
                     .chain(original.map(|x| "original: ".to_owned() + &x).into_iter())
                     .collect(),
+                origin: Provenance::default(),
             }];
         }
         if !glosses_is_empty {
@@ -168,21 +172,19 @@ impl From<Dong> for WordEntry {
     }
 }
 
-pub fn get() -> impl Iterator<Item = CommonEntry> {
-    let file = std::fs::File::open("res/dictionary_char_2024-06-17.jsonl").unwrap();
+pub fn get(path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+    let file = std::fs::File::open(path).in_file(path)?;
     let reader = std::io::BufReader::new(file);
-    let mut dongs: Vec<Dong> = reader
-        .lines()
-        .map_while(Result::ok)
-        .map(|line| {
-            let x = serde_json::from_str::<Dong>(&line);
-            if x.is_err() {
-                eprintln!("{}", line);
-                eprintln!("{:?}", x);
-            }
-            x.unwrap()
-        })
-        .collect();
+    let mut dongs: Vec<Dong> = vec![];
+    let mut lines: Vec<u64> = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.at(path, i as u64 + 1)?;
+        let dong = serde_json::from_str::<Dong>(&line).at(path, i as u64 + 1);
+        if let Some(dong) = report.check(dong)? {
+            dongs.push(dong);
+            lines.push(i as u64 + 1);
+        }
+    }
     let comphm: HashMap<char, Vec<char>> = dongs
         .iter()
         .filter(|x| x.utf8.is_some())
@@ -218,8 +220,13 @@ pub fn get() -> impl Iterator<Item = CommonEntry> {
         }
     }
 
-    dongs
+    Ok(dongs
         .into_iter()
-        .map(WordEntry::from)
-        .map(CommonEntry::from)
+        .zip(lines)
+        .map(|(dong, line)| {
+            let mut w = WordEntry::from(dong);
+            w.stamp(&Provenance::line(line));
+            w.into()
+        })
+        .collect())
 }
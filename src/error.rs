@@ -0,0 +1,187 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Io(std::io::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    Toml(toml::de::Error),
+    Bincode(bincode::Error),
+    Anki(genanki_rs::Error),
+    /// A record that could be read but does not make sense
+    Parse(String),
+    /// Names of the audit problems found in more entries than allowed
+    Audit(Vec<String>),
+}
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Io(e) => write!(f, "{}", e),
+            ErrorKind::Csv(e) => write!(f, "{}", e),
+            ErrorKind::Json(e) => write!(f, "{}", e),
+            ErrorKind::Toml(e) => write!(f, "{}", e),
+            ErrorKind::Bincode(e) => write!(f, "{}", e),
+            ErrorKind::Anki(e) => write!(f, "{}", e),
+            ErrorKind::Parse(e) => write!(f, "{}", e),
+            ErrorKind::Audit(e) => write!(f, "audit thresholds exceeded: {}", e.join(", ")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: Box<ErrorKind>,
+    pub file: Option<PathBuf>,
+    /// 1-based
+    pub line: Option<u64>,
+}
+impl Error {
+    pub fn parse<S: Into<String>>(msg: S) -> Self {
+        ErrorKind::Parse(msg.into()).into()
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file.display())?;
+            if let Some(line) = self.line {
+                write!(f, "{}:", line)?;
+            }
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+impl std::error::Error for Error {}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self {
+            kind: Box::new(kind),
+            file: None,
+            line: None,
+        }
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        ErrorKind::Io(e).into()
+    }
+}
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        let line = e.position().map(|p| p.line());
+        Self {
+            line,
+            ..ErrorKind::Csv(e).into()
+        }
+    }
+}
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        ErrorKind::Json(e).into()
+    }
+}
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        ErrorKind::Toml(e).into()
+    }
+}
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        ErrorKind::Bincode(e).into()
+    }
+}
+impl From<genanki_rs::Error> for Error {
+    fn from(e: genanki_rs::Error) -> Self {
+        ErrorKind::Anki(e).into()
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Adds the location of the problem to errors
+pub trait Context<T> {
+    fn in_file(self, file: &Path) -> Result<T>;
+    fn at(self, file: &Path, line: u64) -> Result<T>;
+}
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn in_file(self, file: &Path) -> Result<T> {
+        self.map_err(|e| {
+            let mut e: Error = e.into();
+            e.file.get_or_insert_with(|| file.to_owned());
+            e
+        })
+    }
+    fn at(self, file: &Path, line: u64) -> Result<T> {
+        self.map_err(|e| {
+            let mut e: Error = e.into();
+            e.line.get_or_insert(line);
+            e
+        })
+        .in_file(file)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Stop at the first bad record
+    #[default]
+    Abort,
+    /// Skip bad records, keeping track of them
+    Skip,
+}
+
+/// Decides what happens to bad records and keeps the ones that were skipped
+#[derive(Debug, Default)]
+pub struct ErrorReport {
+    on_error: OnError,
+    skipped: Mutex<Vec<Error>>,
+    warnings: Mutex<Vec<Error>>,
+}
+impl ErrorReport {
+    pub fn new(on_error: OnError) -> Self {
+        Self {
+            on_error,
+            skipped: Mutex::new(vec![]),
+            warnings: Mutex::new(vec![]),
+        }
+    }
+    /// `Ok(None)` if the record is bad but should be skipped
+    pub fn check<T>(&self, r: Result<T>) -> Result<Option<T>> {
+        match r {
+            Ok(x) => Ok(Some(x)),
+            Err(e) if self.on_error == OnError::Skip => {
+                self.skipped.lock().unwrap().push(e);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+    pub fn on_error(&self) -> OnError {
+        self.on_error
+    }
+    pub fn skipped(&self) -> std::sync::MutexGuard<'_, Vec<Error>> {
+        self.skipped.lock().unwrap()
+    }
+    /// Records a problem that does not stop the record from being used, eg: pinyin that could
+    /// not all be read
+    pub fn warn<E: Into<Error>>(&self, e: E, file: &Path, line: Option<u64>) {
+        let mut e: Error = e.into();
+        e.file.get_or_insert_with(|| file.to_owned());
+        e.line = e.line.or(line);
+        self.warnings().push(e);
+    }
+    pub fn warnings(&self) -> std::sync::MutexGuard<'_, Vec<Error>> {
+        self.warnings.lock().unwrap()
+    }
+    /// Adds the records skipped and the warnings in `other` to the ones here
+    pub fn append(&self, other: ErrorReport) {
+        let mut skipped = other.skipped.into_inner().unwrap();
+        self.skipped().append(&mut skipped);
+        let mut warnings = other.warnings.into_inner().unwrap();
+        self.warnings().append(&mut warnings);
+    }
+}
@@ -0,0 +1,186 @@
+//! Explains why an entry ends up where it does in the deck
+use crate::common::*;
+use crate::graph::Cycle;
+use crate::process::*;
+use ordered_float::NotNan;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Finds the entry a word given on the command line refers to
+pub fn find_id(hm: &HashMap<EntryId, CommonEntry>, word: &str) -> Option<EntryId> {
+    [
+        EntryId::Word(word.to_owned()),
+        EntryId::Syllable(word.to_owned()),
+        EntryId::Grammar(word.to_owned()),
+    ]
+    .into_iter()
+    .find(|x| hm.contains_key(x))
+}
+
+fn describe(id: &EntryId, placements: &HashMap<EntryId, Placement>) -> String {
+    match placements.get(id) {
+        Some(p) => format!("{} (#{})", id, p.position + 1),
+        None => format!("{} (not in deck)", id),
+    }
+}
+
+/// Human readable explanation of the priority of the entry, how it got its position and which
+/// entries it made room for.
+/// `hm` and `placements` are the ones given to and returned by `order_entries`, `cycles` the ones
+/// broken in the graph it was given
+pub fn explain(
+    hm: &HashMap<EntryId, CommonEntry>,
+    placements: &HashMap<EntryId, Placement>,
+    cycles: &[Cycle],
+    args: &ProcessOptions,
+    id: &EntryId,
+) -> String {
+    let mut s = String::new();
+    let Some(entry) = hm.get(id) else {
+        return format!(
+            "{}: not found, either no source has it or it was removed for lacking both a \
+             definition and an HSK level",
+            id
+        );
+    };
+    let priority = entry.priority();
+    writeln!(s, "{}: priority {:.4}", id, priority).unwrap();
+
+    match entry {
+        CommonEntry::WordEntry(w) => {
+            for (name, value) in w.score.parts.iter() {
+                writeln!(s, "  {:<10} {:.4}", name, value).unwrap();
+            }
+            match w.hsk_lev {
+                Some(lev) => writeln!(s, "    hsk level {}", lev).unwrap(),
+                None => writeln!(s, "    no hsk level").unwrap(),
+            }
+            let total: NotNan<f32> = w.freq.iter().map(|x| x.value).sum();
+            for f in w.freq.iter() {
+                let share = if *total > 0f32 {
+                    *f.value / *total
+                } else {
+                    0f32
+                };
+                writeln!(
+                    s,
+                    "    frequency {:.3e} from {} ({:.0}%)",
+                    *f.value,
+                    f.origin,
+                    share * 100f32
+                )
+                .unwrap();
+            }
+            if w.freq.is_empty() {
+                writeln!(s, "    no frequency").unwrap();
+            }
+        }
+        CommonEntry::SyllableEntry(_) => {
+            writeln!(s, "  syllables have a fixed priority").unwrap();
+        }
+        CommonEntry::GrammarEntry(g) => {
+            for (name, value) in g.score.parts.iter() {
+                writeln!(s, "  {:<10} {:.4}", name, value).unwrap();
+            }
+            writeln!(
+                s,
+                "    hsk level {:?}, position within the level {:?}",
+                g.hsk_lev, g.hsk_sublev
+            )
+            .unwrap();
+        }
+    }
+
+    match placements.get(id) {
+        Some(p) => {
+            writeln!(s, "position #{} of {}", p.position + 1, placements.len()).unwrap();
+            if p.pulled_by.is_empty() {
+                writeln!(s, "placed because of its own priority").unwrap();
+            } else {
+                writeln!(s, "pulled forward as a dependency of:").unwrap();
+                for (depth, a) in p.pulled_by.iter().enumerate() {
+                    let priority = hm.get(a).map(|x| x.priority()).unwrap_or_default();
+                    writeln!(
+                        s,
+                        "  {}{} priority {:.4}",
+                        "  ".repeat(depth),
+                        describe(a, placements),
+                        priority
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        None => {
+            let rank = hm.values().filter(|x| x.priority() > priority).count();
+            if *priority < args.min_priority {
+                writeln!(
+                    s,
+                    "not in deck: priority is below the minimum of {}",
+                    args.min_priority
+                )
+                .unwrap();
+            } else if rank >= args.max_entries {
+                writeln!(
+                    s,
+                    "not in deck: ranks #{} by priority, the deck holds {} entries",
+                    rank + 1,
+                    args.max_entries
+                )
+                .unwrap();
+            } else {
+                writeln!(
+                    s,
+                    "not in deck: ranks #{} by priority, but dependencies of higher priority \
+                     entries filled the {} places",
+                    rank + 1,
+                    args.max_entries
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    for (from, to) in cycles.iter().flat_map(|x| x.broken.iter()) {
+        if from == id || to == id {
+            writeln!(
+                s,
+                "dependency of {} on {} ignored to break a cycle",
+                from, to
+            )
+            .unwrap();
+        }
+    }
+
+    let mut unlocked: Vec<(&EntryId, &Placement)> = hm
+        .iter()
+        .filter(|(_, e)| e.dependencies().contains(id))
+        .filter(|(k, _)| {
+            !cycles
+                .iter()
+                .any(|c| c.broken.iter().any(|(from, to)| from == *k && to == id))
+        })
+        .filter_map(|(k, _)| placements.get_key_value(k))
+        .collect();
+    unlocked.sort_by_key(|(_, p)| p.position);
+    if !unlocked.is_empty() {
+        writeln!(s, "unlocks:").unwrap();
+        for (k, _) in unlocked {
+            let pulled = placements
+                .get(id)
+                .is_some_and(|x| x.pulled_by.last() == Some(k));
+            writeln!(
+                s,
+                "  {}{}",
+                describe(k, placements),
+                if pulled {
+                    ", which pulled it forward"
+                } else {
+                    ""
+                }
+            )
+            .unwrap();
+        }
+    }
+    s
+}
@@ -1,6 +1,9 @@
 use crate::common::*;
+use crate::error::*;
+use crate::utils::read_csv;
 use ordered_float::NotNan;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct FreqRecord {
@@ -15,22 +18,24 @@ impl From<FreqRecord> for WordEntry {
     fn from(r: FreqRecord) -> Self {
         let freq = r.wm / 1000000f32;
         let mut w = WordEntry::from_id(r.word);
-        w.freq = vec![freq];
+        w.freq = vec![freq.into()];
+        w.pos = Some(PartOfSpeech::from_ictclas(&r.pos));
         w
     }
 }
 
-pub fn get_records() -> impl Iterator<Item = CommonEntry> {
-    let file = std::fs::File::open("res/SUBTLEX-CH.txt").unwrap();
+pub fn get_records(path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+    let file = std::fs::File::open(path).in_file(path)?;
     let reader = std::io::BufReader::new(file);
-    let mut rdr = csv::ReaderBuilder::new()
+    let rdr = csv::ReaderBuilder::new()
         .delimiter(b'\t')
         .from_reader(reader);
-    let v: Vec<CommonEntry> = rdr
-        .deserialize::<FreqRecord>()
-        .map(|r| r.unwrap())
-        .map(WordEntry::from)
-        .map(CommonEntry::from)
-        .collect();
-    v.into_iter()
+    Ok(read_csv::<FreqRecord, _>(rdr, path, report)?
+        .into_iter()
+        .map(|(line, r)| {
+            let mut w = WordEntry::from(r);
+            w.stamp(&Provenance::line(line));
+            w.into()
+        })
+        .collect())
 }
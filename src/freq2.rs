@@ -1,7 +1,9 @@
 use crate::common::*;
+use crate::error::*;
 use ordered_float::NotNan;
 use serde::{Deserialize, Serialize};
 use std::io::BufRead;
+use std::path::Path;
 use std::str::FromStr;
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -12,29 +14,38 @@ pub struct FreqRecord {
 impl From<FreqRecord> for WordEntry {
     fn from(r: FreqRecord) -> Self {
         let mut w = WordEntry::from_id(r.id);
-        w.freq = vec![r.freq];
+        w.freq = vec![r.freq.into()];
         w
     }
 }
 
-pub fn get_records() -> impl Iterator<Item = CommonEntry> {
-    let file = std::fs::File::open("res/zh_cn_50k.txt").unwrap();
-    let reader = std::io::BufReader::new(file).lines().map_while(Result::ok);
-    let a: Vec<(String, u32)> = reader
-        .map(|x| {
-            let mut s = x.split_whitespace();
-            (
-                s.next().unwrap().to_owned(),
-                u32::from_str(s.next().unwrap()).unwrap(),
-            )
-        })
-        .collect();
-    let tot: u32 = a.iter().map(|x| x.1).sum();
-    a.into_iter()
-        .map(move |x| FreqRecord {
-            id: x.0,
-            freq: NotNan::new(x.1 as f32 / tot as f32).unwrap(),
+fn parse_line(line: &str) -> Result<(String, u32)> {
+    let mut s = line.split_whitespace();
+    let word = s.next().ok_or_else(|| Error::parse("missing word"))?;
+    let count = s.next().ok_or_else(|| Error::parse("missing count"))?;
+    let count = u32::from_str(count).map_err(|e| Error::parse(format!("bad count: {}", e)))?;
+    Ok((word.to_owned(), count))
+}
+
+pub fn get_records(path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+    let file = std::fs::File::open(path).in_file(path)?;
+    // line of each word, with its count
+    let mut a: Vec<(u64, String, u32)> = vec![];
+    for (i, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line.at(path, i as u64 + 1)?;
+        if let Some((word, count)) = report.check(parse_line(&line).at(path, i as u64 + 1))? {
+            a.push((i as u64 + 1, word, count));
+        }
+    }
+    let tot: u32 = a.iter().map(|x| x.2).sum();
+    Ok(a.into_iter()
+        .map(move |(line, id, count)| {
+            let mut w = WordEntry::from(FreqRecord {
+                id,
+                freq: NotNan::new(count as f32 / tot as f32).unwrap(),
+            });
+            w.stamp(&Provenance::line(line));
+            CommonEntry::from(w)
         })
-        .map(WordEntry::from)
-        .map(CommonEntry::from)
+        .collect())
 }
@@ -0,0 +1,335 @@
+//! Dependency graph between entries.
+//! Sources can make entries depend on each other in circles (eg: a component listing the
+//! character it is part of), the cycles are found and broken here so entries can be ordered.
+use crate::common::*;
+use crate::error::*;
+use ordered_float::NotNan;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A source that said an entry depends on another one
+#[derive(Clone, Debug)]
+pub struct EdgeOrigin {
+    pub source: String,
+    pub precedence: i32,
+}
+
+/// Sources of each edge, keyed on (dependent, dependency)
+pub type EdgeOrigins = HashMap<(EntryId, EntryId), Vec<EdgeOrigin>>;
+
+/// Which edge of a cycle gets removed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CyclePolicy {
+    /// The edge to the dependency with the lowest priority
+    #[default]
+    LowestPriority,
+    /// The edge coming from the sources with the lowest precedence
+    SourcePrecedence,
+}
+impl std::str::FromStr for CyclePolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lowest-priority" => Ok(Self::LowestPriority),
+            "source-precedence" => Ok(Self::SourcePrecedence),
+            _ => Err(Error::parse(format!(
+                "unknown cycle policy {}, expected lowest-priority or source-precedence",
+                s
+            ))),
+        }
+    }
+}
+impl fmt::Display for CyclePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::LowestPriority => write!(f, "lowest-priority"),
+            Self::SourcePrecedence => write!(f, "source-precedence"),
+        }
+    }
+}
+
+/// A strongly connected component of the graph, that is a group of entries each depending
+/// (maybe indirectly) on all the others
+#[derive(Clone, Debug)]
+pub struct Cycle {
+    pub entries: Vec<EntryId>,
+    /// Edges between the entries, as (dependent, dependency)
+    pub edges: Vec<(EntryId, EntryId)>,
+    /// Edges removed to break the cycle
+    pub broken: Vec<(EntryId, EntryId)>,
+}
+
+pub struct DependencyGraph {
+    ids: Vec<EntryId>,
+    index: HashMap<EntryId, usize>,
+    /// Dependencies of each entry
+    adj: Vec<Vec<usize>>,
+    pub origins: EdgeOrigins,
+}
+
+/// Strongly connected components, using Tarjan's algorithm without recursion since dependency
+/// chains can be long
+fn tarjan(adj: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let n = adj.len();
+    let mut index = vec![usize::MAX; n];
+    let mut low = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = vec![];
+    let mut sccs = vec![];
+    let mut next = 0;
+    for root in 0..n {
+        if index[root] != usize::MAX {
+            continue;
+        }
+        // node and how many of its dependencies were visited
+        let mut calls = vec![(root, 0)];
+        index[root] = next;
+        low[root] = next;
+        next += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        while let Some(top) = calls.last_mut() {
+            let v = top.0;
+            if top.1 < adj[v].len() {
+                let w = adj[v][top.1];
+                top.1 += 1;
+                if index[w] == usize::MAX {
+                    index[w] = next;
+                    low[w] = next;
+                    next += 1;
+                    stack.push(w);
+                    on_stack[w] = true;
+                    calls.push((w, 0));
+                } else if on_stack[w] {
+                    low[v] = low[v].min(index[w]);
+                }
+            } else {
+                calls.pop();
+                if let Some(&(u, _)) = calls.last() {
+                    low[u] = low[u].min(low[v]);
+                }
+                if low[v] == index[v] {
+                    let mut scc = vec![];
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        scc.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    sccs.push(scc);
+                }
+            }
+        }
+    }
+    sccs
+}
+
+impl DependencyGraph {
+    /// Dependencies on entries missing from `hm` are left out
+    pub fn new(hm: &HashMap<EntryId, CommonEntry>, origins: EdgeOrigins) -> Self {
+        let mut ids: Vec<EntryId> = hm.keys().cloned().collect();
+        ids.sort();
+        let index: HashMap<EntryId, usize> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.clone(), i))
+            .collect();
+        let adj = ids
+            .iter()
+            .map(|id| {
+                hm[id]
+                    .dependencies()
+                    .iter()
+                    .filter_map(|x| index.get(x).copied())
+                    .collect()
+            })
+            .collect();
+        Self {
+            ids,
+            index,
+            adj,
+            origins,
+        }
+    }
+    pub fn dependencies(&self, id: &EntryId) -> impl Iterator<Item = &EntryId> {
+        self.index
+            .get(id)
+            .into_iter()
+            .flat_map(|&i| self.adj[i].iter().map(|&j| &self.ids[j]))
+    }
+    pub fn origins(&self, from: &EntryId, to: &EntryId) -> &[EdgeOrigin] {
+        self.origins
+            .get(&(from.clone(), to.clone()))
+            .map(|x| x.as_slice())
+            .unwrap_or_default()
+    }
+    fn is_cycle(&self, scc: &[usize]) -> bool {
+        scc.len() > 1 || self.adj[scc[0]].contains(&scc[0])
+    }
+    /// Edges between the given entries
+    fn edges_within(&self, nodes: &[usize]) -> Vec<(usize, usize)> {
+        let set: HashSet<usize> = nodes.iter().copied().collect();
+        let mut edges: Vec<(usize, usize)> = nodes
+            .iter()
+            .flat_map(|&v| {
+                self.adj[v]
+                    .iter()
+                    .filter(|w| set.contains(w))
+                    .map(move |&w| (v, w))
+            })
+            .collect();
+        edges.sort();
+        edges
+    }
+    /// The strongly connected components that contain a cycle
+    pub fn cycles(&self) -> Vec<Cycle> {
+        let mut cycles: Vec<Cycle> = tarjan(&self.adj)
+            .into_iter()
+            .filter(|x| self.is_cycle(x))
+            .map(|mut scc| {
+                scc.sort();
+                Cycle {
+                    entries: scc.iter().map(|&i| self.ids[i].clone()).collect(),
+                    edges: self
+                        .edges_within(&scc)
+                        .into_iter()
+                        .map(|(v, w)| (self.ids[v].clone(), self.ids[w].clone()))
+                        .collect(),
+                    broken: vec![],
+                }
+            })
+            .collect();
+        cycles.sort_by(|a, b| a.entries.cmp(&b.entries));
+        cycles
+    }
+    /// Removes edges until there are no cycles left, the edge removed from each cycle is chosen
+    /// according to `policy`.
+    /// Returns the cycles that were found, with the edges that were removed from them
+    pub fn break_cycles(
+        &mut self,
+        hm: &HashMap<EntryId, CommonEntry>,
+        policy: CyclePolicy,
+    ) -> Vec<Cycle> {
+        let priority = |i: usize| hm[&self.ids[i]].priority();
+        let mut cycles = self.cycles();
+        for cycle in cycles.iter_mut() {
+            let mut todo = vec![cycle.entries.iter().map(|x| self.index[x]).collect()];
+            while let Some(nodes) = todo.pop() {
+                let nodes: Vec<usize> = nodes;
+                // every edge inside a strongly connected component is part of a cycle,
+                // so removing the weakest one is enough to make progress
+                let weakest = self
+                    .edges_within(&nodes)
+                    .into_iter()
+                    .min_by_key(|&(v, w)| {
+                        let precedence = match policy {
+                            CyclePolicy::LowestPriority => 0,
+                            CyclePolicy::SourcePrecedence => self
+                                .origins(&self.ids[v], &self.ids[w])
+                                .iter()
+                                .map(|x| x.precedence)
+                                .max()
+                                .unwrap_or(i32::MIN),
+                        };
+                        let p: (NotNan<f32>, NotNan<f32>) = (priority(w), priority(v));
+                        (precedence, p, v, w)
+                    })
+                    .unwrap();
+                let (v, w) = weakest;
+                self.adj[v].retain(|&x| x != w);
+                cycle
+                    .broken
+                    .push((self.ids[v].clone(), self.ids[w].clone()));
+
+                // look for what is left of the cycle
+                let local: HashMap<usize, usize> =
+                    nodes.iter().enumerate().map(|(i, &x)| (x, i)).collect();
+                let sub_adj: Vec<Vec<usize>> = nodes
+                    .iter()
+                    .map(|&x| {
+                        self.adj[x]
+                            .iter()
+                            .filter_map(|y| local.get(y).copied())
+                            .collect()
+                    })
+                    .collect();
+                for scc in tarjan(&sub_adj) {
+                    let scc: Vec<usize> = scc.into_iter().map(|i| nodes[i]).collect();
+                    if self.is_cycle(&scc) {
+                        todo.push(scc);
+                    }
+                }
+            }
+        }
+        cycles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::explain::explain;
+    use crate::process::*;
+    use crate::scoring::*;
+
+    fn word(id: &str, hsk_lev: u8, deps: &[&str]) -> (EntryId, CommonEntry) {
+        let mut w = WordEntry::from_id(id.into());
+        w.hsk_lev = Some(hsk_lev);
+        w.dependencies = deps.iter().map(|x| EntryId::Word(x.to_string())).collect();
+        let mut e = w.into();
+        score_entry(&WeightedScorer::default(), &mut e);
+        (e.id(), e)
+    }
+
+    #[test]
+    fn break_cycles() {
+        let hm: HashMap<EntryId, CommonEntry> = [
+            word("口", 1, &["品"]),
+            word("品", 5, &["口"]),
+            word("吅", 6, &["口", "吅"]),
+            word("人", 1, &[]),
+        ]
+        .into_iter()
+        .collect();
+        let mut graph = DependencyGraph::new(&hm, EdgeOrigins::new());
+        let cycles = graph.break_cycles(&hm, CyclePolicy::LowestPriority);
+        assert_eq!(cycles.len(), 2);
+        let id = |x: &str| EntryId::Word(x.into());
+        assert_eq!(cycles[0].broken, vec![(id("口"), id("品"))]);
+        assert_eq!(cycles[1].broken, vec![(id("吅"), id("吅"))]);
+        assert!(graph.cycles().is_empty());
+        assert_eq!(
+            graph.dependencies(&id("吅")).collect::<Vec<_>>(),
+            vec![&id("口")]
+        );
+    }
+
+    #[test]
+    fn order_with_cycle_left() {
+        let hm: HashMap<EntryId, CommonEntry> = [word("口", 1, &["品"]), word("品", 1, &["口"])]
+            .into_iter()
+            .collect();
+        let graph = DependencyGraph::new(&hm, EdgeOrigins::new());
+        let (ans, _) = order_entries(&hm, &graph, &ProcessOptions::default());
+        assert_eq!(ans.len(), 2);
+    }
+
+    #[test]
+    fn explain_leaves_out_broken_edges() {
+        let hm: HashMap<EntryId, CommonEntry> = [word("口", 1, &["品"]), word("品", 5, &["口"])]
+            .into_iter()
+            .collect();
+        let mut graph = DependencyGraph::new(&hm, EdgeOrigins::new());
+        let cycles = graph.break_cycles(&hm, CyclePolicy::LowestPriority);
+        let args = ProcessOptions::default();
+        let (_, placements) = order_entries(&hm, &graph, &args);
+        let id = |x: &str| EntryId::Word(x.into());
+        // 口 no longer depends on 品
+        let text = explain(&hm, &placements, &cycles, &args, &id("品"));
+        assert!(!text.contains("unlocks"), "{}", text);
+        let text = explain(&hm, &placements, &cycles, &args, &id("口"));
+        assert!(text.contains("unlocks:\n  品"), "{}", text);
+    }
+}
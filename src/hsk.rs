@@ -1,6 +1,10 @@
 use crate::common::*;
+use crate::error::*;
+use crate::pinyin_type::read_pinyin;
+use crate::utils::read_csv;
 use serde::Deserialize;
 use std::fs::File;
+use std::path::Path;
 
 #[derive(Clone, Deserialize)]
 pub struct HskEntry {
@@ -10,51 +14,44 @@ pub struct HskEntry {
     pinyin: String,
     #[allow(unused)]
     def: String,
-    #[serde(default)]
-    level: Option<u8>,
-}
-impl From<HskEntry> for WordEntry {
-    fn from(h: HskEntry) -> Self {
-        let mut w = WordEntry::from_id(h.zh);
-        w.hsk_lev = h.level;
-        w.pinyin = vec![h.pinyin.into()];
-        w
-    }
 }
 
-fn get_hsk(filename: &str, level: u8) -> impl Iterator<Item = HskEntry> {
-    let file = File::open(filename).unwrap();
+fn get_hsk(filename: &Path, level: u8, report: &ErrorReport) -> Result<Vec<WordEntry>> {
+    let file = File::open(filename).in_file(filename)?;
     let reader = std::io::BufReader::new(file);
-    let mut rdr = csv::ReaderBuilder::new()
+    let rdr = csv::ReaderBuilder::new()
         .delimiter(b'\t')
         .has_headers(false)
         .from_reader(reader);
-    let v: Vec<_> = rdr
-        .deserialize::<HskEntry>()
-        .map(move |r| {
-            let mut e = r.unwrap();
-            e.level = Some(level);
-            e
-        })
-        .collect();
-    v.into_iter()
+    let mut v = vec![];
+    for (line, e) in read_csv::<HskEntry, _>(rdr, filename, report)? {
+        let mut w = WordEntry::from_id(e.zh);
+        w.hsk_lev = Some(level);
+        w.pinyin = vec![read_pinyin(&e.pinyin, report, filename, Some(line))];
+        w.stamp(&Provenance::line(line));
+        v.push(w);
+    }
+    Ok(v)
 }
 
-pub fn get_hsks() -> impl Iterator<Item = CommonEntry> {
-    let h1 = get_hsk("res/HSK-3.0/HSK List (Meaning)/HSK 1.tsv", 1);
-    let h2 = get_hsk("res/HSK-3.0/HSK List (Meaning)/HSK 2.tsv", 2);
-    let h3 = get_hsk("res/HSK-3.0/HSK List (Meaning)/HSK 3.tsv", 3);
-    let h4 = get_hsk("res/HSK-3.0/HSK List (Meaning)/HSK 4.tsv", 4);
-    let h5 = get_hsk("res/HSK-3.0/HSK List (Meaning)/HSK 5.tsv", 5);
-    let h6 = get_hsk("res/HSK-3.0/HSK List (Meaning)/HSK 6.tsv", 6);
-    let h789 = get_hsk("res/HSK-3.0/HSK List (Meaning)/HSK 7-9.tsv", 7);
+/// `dir` is the directory containing the HSK 3.0 word lists
+pub fn get_hsks(dir: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+    let h1 = get_hsk(&dir.join("HSK 1.tsv"), 1, report)?;
+    let h2 = get_hsk(&dir.join("HSK 2.tsv"), 2, report)?;
+    let h3 = get_hsk(&dir.join("HSK 3.tsv"), 3, report)?;
+    let h4 = get_hsk(&dir.join("HSK 4.tsv"), 4, report)?;
+    let h5 = get_hsk(&dir.join("HSK 5.tsv"), 5, report)?;
+    let h6 = get_hsk(&dir.join("HSK 6.tsv"), 6, report)?;
+    let h789 = get_hsk(&dir.join("HSK 7-9.tsv"), 7, report)?;
 
-    h1.chain(h2)
+    Ok(h1
+        .into_iter()
+        .chain(h2)
         .chain(h3)
         .chain(h4)
         .chain(h5)
         .chain(h6)
         .chain(h789)
-        .map(WordEntry::from)
         .map(CommonEntry::from)
+        .collect())
 }
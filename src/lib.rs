@@ -0,0 +1,34 @@
+#![feature(path_file_prefix)]
+//! Builds an anki deck for studying mandarin chinese out of several data sources.
+//!
+//! Each data source is a [`sources::DataSource`] producing [`common::CommonEntry`]s,
+//! [`process::process_entries`] merges and orders them and [`anki`] turns them into notes.
+pub mod allsetlearning;
+pub mod anim_cjk;
+pub mod anki;
+pub mod audio;
+pub mod audit;
+pub mod cache;
+pub mod cedict;
+pub mod common;
+pub mod config;
+pub mod diff;
+pub mod dong;
+pub mod error;
+pub mod explain;
+pub mod freq;
+pub mod freq2;
+pub mod graph;
+pub mod hsk;
+pub mod lp_grammar;
+pub mod manifest;
+pub mod pinyin_type;
+pub mod process;
+pub mod reading;
+pub mod scoring;
+pub mod sources;
+pub mod tatoeba;
+pub mod unihan;
+pub mod utils;
+
+pub use error::{Error, Result};
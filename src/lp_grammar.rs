@@ -1,6 +1,8 @@
 use crate::common::*;
+use crate::error::*;
 use crate::pinyin_type::*;
 use serde::Deserialize;
+use std::path::Path;
 use std::str::FromStr;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -22,49 +24,71 @@ struct GrammarRecord {
     _hsk_lev: Option<u8>,
     _hsk_sublev: Option<f32>,
 }
+/// The pinyin of the structure is read by [`get_records`], which reports what is not pinyin
 impl From<GrammarRecord> for GrammarEntry {
     fn from(gr: GrammarRecord) -> Self {
-        let epy = Pinyin::from_hanzi(&gr.example);
+        let origin = Provenance::record(gr.code);
         Self {
             id: gr.id.to_string(),
             structure: Triplet {
                 zh: gr.structure,
-                py: gr.pinyin.into(),
+                py: Pinyin::default(),
                 en: gr.english,
+                origin: origin.clone(),
             },
             example: Triplet {
                 zh: gr.example,
                 en: gr.example_translation,
-                py: epy,
+                py: Pinyin::default(),
+                origin,
             },
             hsk_lev: gr._hsk_lev,
             hsk_sublev: gr._hsk_sublev,
+            score: Default::default(),
         }
     }
 }
 
-pub fn get_records() -> impl Iterator<Item = CommonEntry> {
-    let file = std::fs::File::open("res/lp_grammar.csv").unwrap();
+/// The hsk level is the first number of the code, eg: 3 for "3.12-1"
+fn hsk_level(code: &str) -> Result<u8> {
+    code.split(&['.', '-'])
+        .next()
+        .and_then(|x| u8::from_str(x).ok())
+        .filter(|x| (1..=10).contains(x))
+        .ok_or_else(|| Error::parse(format!("bad code: {}", code)))
+}
+
+pub fn get_records(path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+    let file = std::fs::File::open(path).in_file(path)?;
     let reader = std::io::BufReader::new(file);
     let mut rdr = csv::ReaderBuilder::new().from_reader(reader);
     let mut ans = vec![];
     let mut nhsk = [0u32; 11];
     for result in rdr.deserialize() {
-        let mut record: GrammarRecord = result.unwrap();
-        record._hsk_lev =
-            Some(u8::from_str(record.code.split(&['.', '-']).next().unwrap()).unwrap());
-        nhsk[record._hsk_lev.unwrap() as usize] += 1;
-        ans.push(record);
+        let record = result.in_file(path).and_then(|mut record: GrammarRecord| {
+            record._hsk_lev = Some(hsk_level(&record.code).in_file(path)?);
+            Ok(record)
+        });
+        if let Some(record) = report.check(record)? {
+            nhsk[record._hsk_lev.unwrap() as usize] += 1;
+            ans.push(record);
+        }
     }
 
     let mut ihsk = [0u32; 11];
     for record in ans.iter_mut() {
         let hl = record._hsk_lev.unwrap() as usize;
-        record._hsk_sublev = Some(ihsk[hl] as f32 / (nhsk[hl] - 1) as f32);
+        record._hsk_sublev = Some(ihsk[hl] as f32 / (nhsk[hl].max(2) - 1) as f32);
         ihsk[hl] += 1;
     }
 
-    ans.into_iter()
-        .map(GrammarEntry::from)
-        .map(CommonEntry::from)
+    Ok(ans
+        .into_iter()
+        .map(|record| {
+            let py = read_pinyin(&record.pinyin, report, path, None);
+            let mut g = GrammarEntry::from(record);
+            g.structure.py = py;
+            g.into()
+        })
+        .collect())
 }
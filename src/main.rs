@@ -1,234 +1,200 @@
-#![feature(path_file_prefix)]
-mod allsetlearning;
-mod anim_cjk;
-mod anki;
-mod audio;
-mod cedict;
-mod common;
-mod dong;
-mod freq;
-mod freq2;
-mod hsk;
-mod lp_grammar;
-mod pinyin_type;
-mod tatoeba;
-mod unihan;
-mod utils;
-
-use crate::pinyin_type::*;
-use anki::*;
-use common::*;
-use genanki_rs::*;
-use ordered_float::NotNan;
-use utils::*;
-
-const MAX_ENTRIES: usize = 20000;
-// const MAX_ENTRIES: usize = 256;
-const MIN_PRIORITY: f32 = 0.19f32;
-
-use std::collections::{HashMap, HashSet};
-fn process_entries() -> Vec<CommonEntry> {
-    let ag = anim_cjk::parse_graphics_zh_hans();
-    let al = allsetlearning::get();
-    let ad = anim_cjk::parse_dictionary_zh_hans();
-    let cd = cedict::get_cedict();
-    let fr = freq::get_records();
-    let f2 = freq2::get_records();
-    let wa = audio::get_word_audios();
-    let sa = audio::get_syllable_audios(); //.take(2);
-    let hs = hsk::get_hsks();
-    let lg = lp_grammar::get_records();
-    let dg = dong::get();
-    let uh = unihan::get_records();
-
-    let mut hm = HashMap::<EntryId, CommonEntry>::new();
-    for e in hs
-        .chain(uh)
-        .chain(dg)
-        .chain(al)
-        .chain(ag)
-        .chain(ad)
-        .chain(cd)
-        .chain(fr)
-        .chain(f2)
-        .chain(wa)
-        .chain(sa)
-        .chain(lg)
-    {
-        if let Some(hme) = hm.get_mut(&e.id()) {
-            hme.merge(e);
-        } else {
-            hm.insert(e.id(), e);
-        }
-    }
-
-    {
-        // add writings
-        let keys: Vec<_> = hm
-            .keys()
-            .filter(|k| {
-                if let EntryId::Word(w) = k {
-                    w.chars().count() > 1
-                } else {
-                    false
-                }
-            })
-            .cloned()
-            .collect();
-        for key in keys {
-            if let EntryId::Word(ref w) = key {
-                let wr = w
-                    .chars()
-                    .map(|c| {
-                        hm.get(&EntryId::Word(c.into()))
-                            .map(|x| {
-                                if let CommonEntry::WordEntry(w) = x {
-                                    w.writing[0].clone()
-                                } else {
-                                    unreachable!()
-                                }
-                            })
-                            .unwrap_or(CharWriting::Char(c))
-                    })
-                    .collect();
-
-                if let CommonEntry::WordEntry(w) = hm.get_mut(&key).unwrap() {
-                    w.writing = wr;
-                }
-            }
+mod cli;
+
+use ankizh::audit::*;
+use ankizh::common::*;
+use ankizh::diff::load_build;
+use ankizh::error::*;
+use ankizh::explain::find_id;
+use ankizh::graph::*;
+use ankizh::manifest::*;
+use ankizh::process::*;
+use ankizh::sources::*;
+use clap::Parser;
+use cli::*;
+
+fn cache_entries(registry: &SourceRegistry, args: &CacheArgs, report: &ErrorReport) -> Result<()> {
+    let config = args.process.config(registry)?;
+    let entries = process_entries(registry, &config, &args.process.options(&config)?, report)?;
+    write_entries(&args.output, &entries)
+}
+fn get_entries(
+    registry: &SourceRegistry,
+    args: &EntriesArgs,
+    report: &ErrorReport,
+) -> Result<Vec<CommonEntry>> {
+    match args.from_cache {
+        Some(ref path) => read_entries(path),
+        None => {
+            let config = args.process.config(registry)?;
+            process_entries(registry, &config, &args.process.options(&config)?, report)
         }
     }
-    // add definitions to some single-character entries from unicode names
-    // and pinyin to words missing them
-    for (_, entry) in hm.iter_mut() {
-        if let CommonEntry::WordEntry(w) = entry {
-            if w.pinyin.is_empty() {
-                w.pinyin.push(Pinyin::from_hanzi(&w.id));
-            }
-            if w.id.chars().count() == 1 && w.definitions.is_empty() {
-                let c = w.id.chars().next().unwrap();
-                let name = unicode_names2::name(c).unwrap().to_string();
-                if !name.contains(&format!("{:X}", c as u32)) {
-                    w.definitions.push(Definition {
-                        pinyin: None,
-                        english: vec![name],
-                    });
-                }
+}
+/// Prettifies output, used for debugging purposes
+fn debug_entries(entries: Vec<CommonEntry>, provenance: bool) {
+    for entry in entries {
+        println!("{}", entry.compact_display());
+        if let (true, CommonEntry::WordEntry(w)) = (provenance, &entry) {
+            for line in w.provenance() {
+                println!("  {}", line);
             }
         }
     }
-    hm.retain(|_k, v| !v.to_delete());
+}
 
-    let mut ordered: Vec<(NotNan<f32>, EntryId)> = hm
-        .iter()
-        .map(|(k, v)| (v.priority(), k.clone()))
-        .filter(|(p, _k)| *p >= NotNan::new(MIN_PRIORITY).unwrap())
-        .collect();
-    ordered.sort_by_key(|e| e.0);
-    ordered = ordered.into_iter().rev().take(MAX_ENTRIES).rev().collect();
+fn print_ids(title: &str, ids: &[EntryId]) {
+    if !ids.is_empty() {
+        let ids: Vec<String> = ids.iter().map(|x| x.to_string()).collect();
+        println!("{} {}: {}", ids.len(), title, ids.join(" "));
+    }
+}
 
-    let mut ans = vec![];
-    let mut done = HashSet::<EntryId>::new();
-    let mut stack: Vec<Vec<EntryId>> = vec![ordered.into_iter().map(|(_p, k)| k).collect()];
-    let mut ancestors = vec![];
-    ancestors.push(stack[0].last().unwrap().clone());
-    while !stack.is_empty() && ans.len() < MAX_ENTRIES {
-        while stack.last().is_some_and(|x| x.is_empty()) {
-            stack.pop();
-        }
-        //eprintln!("{:?}", &stack[1..]);
-        if let Some(lv) = stack.last_mut() {
-            if let Some(eid) = lv.last().cloned() {
-                let e = hm.get(&eid).unwrap().clone();
-                if !done.contains(&e.id()) {
-                    let mut deps: Vec<EntryId> = e
-                        .dependencies()
-                        .into_iter()
-                        .filter(|x| {
-                            !done.contains(x) && hm.contains_key(x) && !ancestors.contains(x)
-                        })
-                        .collect();
-                    if deps.is_empty() {
-                        done.insert(e.id().clone());
-                        ans.push(e);
-                        lv.pop();
-                        ancestors.pop();
-                        if let Some(did) = lv.last() {
-                            ancestors.push(did.clone());
-                        }
-                        //eprintln!("made {}", eid);
-                    } else {
-                        deps.sort_by_cached_key(|a| hm.get(a).unwrap().priority());
-                        //eprintln!("to make a {}:{} i need {:?}", eid, e.priority(), deps);
-                        ancestors.push(deps.last().unwrap().clone());
-                        stack.push(deps);
-                    }
-                } else {
-                    lv.pop();
-                    ancestors.pop();
-                    if let Some(did) = lv.last() {
-                        ancestors.push(did.clone());
-                    }
-                }
-            }
+fn build(registry: &SourceRegistry, args: &BuildArgs, report: &ErrorReport) -> Result<()> {
+    let mut entries = get_entries(registry, &args.entries, report)?;
+    let previous = args.previous.as_deref().map(Manifest::load).transpose()?;
+    let opts = args.package_options();
+    let manifest = Manifest::new(&entries, previous.as_ref(), &opts)?;
+    if let Some(ref previous) = previous {
+        let changes = Changes::between(previous, &manifest);
+        print_ids("added", &changes.added);
+        print_ids("removed", &changes.removed);
+        print_ids("changed", &changes.changed);
+        println!("{} moved", changes.moved.len());
+        if args.changes_only {
+            let to_import = changes.to_import();
+            entries.retain(|x| to_import.contains(&x.id()));
         }
     }
-
-    tatoeba::add_examples(&mut ans);
-    ans
+    ankizh::anki::write_package(entries, &manifest, &opts, &args.output)?;
+    manifest.write(&args.manifest_path())
 }
 
-/// Prettifies output, used for debugging purposes
-#[allow(unused)]
-fn cache_entries() {
-    let entries = process_entries();
-    let file = std::fs::File::create("out/cache.bin").unwrap();
-    let writer = std::io::BufWriter::new(file);
-    bincode::serialize_into(writer, &entries).unwrap();
-}
-#[allow(unused)]
-fn get_cached_entries() -> Vec<CommonEntry> {
-    let file = std::fs::File::open("out/cache.bin").unwrap();
-    let reader = std::io::BufReader::new(file);
-    bincode::deserialize_from(reader).unwrap()
+fn explain(registry: &SourceRegistry, args: &ExplainArgs, report: &ErrorReport) -> Result<()> {
+    let config = args.process.config(registry)?;
+    let options = args.process.options(&config)?;
+    let (hm, origins) = load_entries(registry, &config, &options, report)?;
+    let mut graph = DependencyGraph::new(&hm, origins);
+    let cycles = graph.break_cycles(&hm, options.cycle_policy);
+    let (_, placements) = order_entries(&hm, &graph, &options);
+    let id = find_id(&hm, &args.word).unwrap_or(EntryId::Word(args.word.clone()));
+    print!(
+        "{}",
+        ankizh::explain::explain(&hm, &placements, &cycles, &options, &id)
+    );
+    Ok(())
 }
-#[allow(unused)]
-fn debug_entries(entries: Vec<CommonEntry>) {
-    for entry in entries {
-        println!("{}", entry.compact_display());
+
+fn cycles(registry: &SourceRegistry, args: &CyclesArgs, report: &ErrorReport) -> Result<()> {
+    let config = args.process.config(registry)?;
+    let options = args.process.options(&config)?;
+    let (hm, origins) = load_entries(registry, &config, &options, report)?;
+    let mut graph = DependencyGraph::new(&hm, origins);
+    let cycles = graph.break_cycles(&hm, options.cycle_policy);
+    if cycles.is_empty() {
+        println!("no cycles");
     }
+    for cycle in cycles {
+        let names: Vec<String> = cycle.entries.iter().map(|x| x.to_string()).collect();
+        println!("cycle between {}", names.join(" "));
+        for (from, to) in cycle.edges.iter() {
+            let sources: Vec<&str> = graph
+                .origins(from, to)
+                .iter()
+                .map(|x| x.source.as_str())
+                .collect();
+            let broken = cycle.broken.contains(&(from.clone(), to.clone()));
+            println!(
+                "  {} -> {} from {}{}",
+                from,
+                to,
+                sources.join(", "),
+                if broken { " (removed)" } else { "" }
+            );
+        }
+    }
+    Ok(())
 }
 
-fn main() {
-    //cache_entries();return;
-    //let entries = get_cached_entries();
-    let entries = process_entries();
-    //debug_entries(entries);
-    //return;
+fn diff(args: &DiffArgs) -> Result<()> {
+    let old = load_build(&args.old)?;
+    let new = load_build(&args.new)?;
+    let d = ankizh::diff::diff(&old, &new, args.min_shift);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&d)?);
+    } else {
+        print!("{}", d.to_text());
+    }
+    Ok(())
+}
 
-    let media: Vec<String> = entries.iter().flat_map(|x| x.media()).collect();
+fn audit(registry: &SourceRegistry, args: &AuditArgs, report: &ErrorReport) -> Result<()> {
+    let config = args.entries.process.config(registry)?;
+    let mut thresholds = config.audit_thresholds()?;
+    for (name, max) in args.max.iter() {
+        thresholds.insert(Problem::from_name(name)?, *max);
+    }
+    let entries = get_entries(registry, &args.entries, report)?;
+    let audit = Audit::new(&entries);
+    print!("{}", audit.to_text(args.limit));
+    let exceeded = audit.exceeded(&thresholds);
+    for (p, count) in exceeded.iter() {
+        eprintln!(
+            "{}: {} entries, more than {}",
+            p.name(),
+            count,
+            thresholds[p]
+        );
+    }
+    if exceeded.is_empty() {
+        Ok(())
+    } else {
+        let names = exceeded.iter().map(|(p, _)| p.name().to_owned()).collect();
+        Err(ErrorKind::Audit(names).into())
+    }
+}
 
-    let mut guids = HashSet::<String>::new();
-    for entry in entries.iter() {
-        let guid = guid_for(entry.id());
-        if !guids.insert(guid) {
-            panic!("GUID collision");
+fn main() -> std::process::ExitCode {
+    let cli = Cli::parse();
+    let registry = SourceRegistry::default();
+    let on_error = match cli.command {
+        Command::Build(ref args) => args.entries.process.on_error(),
+        Command::Inspect(ref args) => args.entries.process.on_error(),
+        Command::Cache(ref args) => args.process.on_error(),
+        Command::Explain(ref args) => args.process.on_error(),
+        Command::Cycles(ref args) => args.process.on_error(),
+        Command::Diff(_) => OnError::Abort,
+        Command::Audit(ref args) => args.entries.process.on_error(),
+    };
+    let report = ErrorReport::new(on_error);
+    let res = match cli.command {
+        Command::Build(args) => build(&registry, &args, &report),
+        Command::Inspect(args) => get_entries(&registry, &args.entries, &report)
+            .map(|entries| debug_entries(entries, args.provenance)),
+        Command::Cache(args) => cache_entries(&registry, &args, &report),
+        Command::Explain(args) => explain(&registry, &args, &report),
+        Command::Cycles(args) => cycles(&registry, &args, &report),
+        Command::Diff(args) => diff(&args),
+        Command::Audit(args) => audit(&registry, &args, &report),
+    };
+    let warnings = report.warnings();
+    if !warnings.is_empty() {
+        eprintln!("{} warnings:", warnings.len());
+        for e in warnings.iter() {
+            eprintln!("  {}", e);
         }
     }
-
-    let notes = entries
-        .into_iter()
-        .enumerate()
-        .map(|(idx, x)| x.into_note(idx));
-
-    let mut deck = Deck::new(DECK_ID, "zh", "zh");
-    for note in notes {
-        deck.add_note(note);
+    let skipped = report.skipped();
+    if !skipped.is_empty() {
+        eprintln!("skipped {} bad records:", skipped.len());
+        for e in skipped.iter() {
+            eprintln!("  {}", e);
+        }
+    }
+    match res {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::ExitCode::FAILURE
+        }
     }
-
-    let mut package = Package::new(vec![deck], media.iter().map(|x| x.as_str()).collect()).unwrap();
-
-    let file = std::fs::File::create("out/test.apkg").unwrap();
-    let writer = std::io::BufWriter::new(file);
-    package.write(writer).unwrap();
 }
@@ -0,0 +1,287 @@
+//! Record of what went in a package, so the next build can keep the GUIDs of its notes.
+//! Anki keeps the review history of a note as long as its GUID does not change, the manifest makes
+//! GUIDs independent of how they were first derived from the entry id.
+use crate::anki::{note_content, PackageOptions};
+use crate::common::*;
+use crate::error::*;
+use crate::utils::*;
+use core::hash::Hasher;
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub id: EntryId,
+    pub guid: String,
+    /// Position in the deck, used as the sort field
+    pub position: usize,
+    /// Hash of the content of the entry, to find the ones that changed
+    pub hash: String,
+    pub summary: EntrySummary,
+}
+
+/// The parts of an entry worth comparing between builds
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EntrySummary {
+    pub pinyin: Vec<String>,
+    pub definitions: Vec<String>,
+    pub hsk_lev: Option<u8>,
+    pub media: Vec<String>,
+}
+impl From<&CommonEntry> for EntrySummary {
+    fn from(entry: &CommonEntry) -> Self {
+        let (pinyin, definitions, hsk_lev) = match entry {
+            CommonEntry::WordEntry(w) => (
+                w.pinyin.iter().map(|x| x.to_string()).collect(),
+                w.definitions
+                    .iter()
+                    .map(|x| match x.pinyin {
+                        Some(ref py) => format!("{}: {}", py, x.english.join("; ")),
+                        None => x.english.join("; "),
+                    })
+                    .collect(),
+                w.hsk_lev,
+            ),
+            CommonEntry::SyllableEntry(s) => (vec![s.id.to_string()], vec![], None),
+            CommonEntry::GrammarEntry(g) => (
+                vec![g.structure.py.to_string()],
+                vec![g.structure.en.clone()],
+                g.hsk_lev,
+            ),
+        };
+        Self {
+            pinyin,
+            definitions,
+            hsk_lev,
+            media: entry.media(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Hash of what ends up in the note with these options: its summary, fields and tags.
+/// Data that is not shown, like the score or the lines entries were read from (unless
+/// provenance is shown), does not count
+pub fn content_hash(entry: &CommonEntry, opts: &PackageOptions) -> String {
+    let content = note_content(entry, opts);
+    let mut h = SipHasher::new();
+    h.write(&bincode::serialize(&EntrySummary::from(entry)).unwrap());
+    h.write(&bincode::serialize(&(content.fields, content.tags)).unwrap());
+    format!("{:016x}", h.finish())
+}
+
+/// Gives `guid` to `id`, unless another entry has it
+fn claim(owners: &mut HashMap<String, EntryId>, guid: &str, id: &EntryId) -> Result<()> {
+    match owners.get(guid) {
+        Some(owner) if owner != id => Err(Error::parse(format!(
+            "GUID {} of {} is already used by {}",
+            guid, id, owner
+        ))),
+        Some(_) => Ok(()),
+        None => {
+            owners.insert(guid.to_owned(), id.clone());
+            Ok(())
+        }
+    }
+}
+
+impl Manifest {
+    /// Manifest of the entries, in order.
+    /// Entries already in `previous` keep their GUID, new ones get one derived from their id.
+    /// Fails when a GUID would be given to two entries, anki would merge their notes.
+    /// `opts` are the ones the package is written with, the content of notes depends on them
+    pub fn new(
+        entries: &[CommonEntry],
+        previous: Option<&Manifest>,
+        opts: &PackageOptions,
+    ) -> Result<Self> {
+        let old: HashMap<&EntryId, &str> = previous
+            .iter()
+            .flat_map(|x| x.entries.iter())
+            .map(|x| (&x.id, x.guid.as_str()))
+            .collect();
+        // GUIDs of removed entries stay taken, their notes might still be in the collection
+        let mut owners: HashMap<String, EntryId> = HashMap::new();
+        for e in previous.iter().flat_map(|x| x.entries.iter()) {
+            claim(&mut owners, &e.guid, &e.id)?;
+        }
+        let mut ans = vec![];
+        for (position, entry) in entries.iter().enumerate() {
+            let id = entry.id();
+            let guid = match old.get(&id) {
+                Some(guid) => guid.to_string(),
+                None => {
+                    let guid = guid_for(&id);
+                    claim(&mut owners, &guid, &id)?;
+                    guid
+                }
+            };
+            ans.push(ManifestEntry {
+                id,
+                guid,
+                position,
+                hash: content_hash(entry, opts),
+                summary: entry.into(),
+            });
+        }
+        Ok(Self { entries: ans })
+    }
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path).in_file(path)?;
+        serde_json::from_reader(std::io::BufReader::new(file)).in_file(path)
+    }
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let writer = std::io::BufWriter::new(create_file(path)?);
+        serde_json::to_writer(writer, self).in_file(path)
+    }
+}
+
+/// What changed between two builds
+#[derive(Clone, Debug, Default)]
+pub struct Changes {
+    pub added: Vec<EntryId>,
+    pub removed: Vec<EntryId>,
+    /// Entries with a different content
+    pub changed: Vec<EntryId>,
+    /// Entries with the same content at another position
+    pub moved: Vec<EntryId>,
+}
+impl Changes {
+    pub fn between(previous: &Manifest, current: &Manifest) -> Self {
+        let old: HashMap<&EntryId, &ManifestEntry> =
+            previous.entries.iter().map(|x| (&x.id, x)).collect();
+        let new: HashSet<&EntryId> = current.entries.iter().map(|x| &x.id).collect();
+        let mut changes = Changes::default();
+        for e in current.entries.iter() {
+            match old.get(&e.id) {
+                None => changes.added.push(e.id.clone()),
+                Some(o) if o.hash != e.hash => changes.changed.push(e.id.clone()),
+                Some(o) if o.position != e.position => changes.moved.push(e.id.clone()),
+                Some(_) => {}
+            }
+        }
+        changes.removed = previous
+            .entries
+            .iter()
+            .filter(|x| !new.contains(&x.id))
+            .map(|x| x.id.clone())
+            .collect();
+        changes
+    }
+    /// Entries that have to be imported for the deck to be up to date, moved entries are not
+    /// included since anki keeps the position of notes it already has
+    pub fn to_import(&self) -> HashSet<EntryId> {
+        self.added
+            .iter()
+            .chain(self.changed.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(id: &str, hsk_lev: u8) -> CommonEntry {
+        let mut w = WordEntry::from_id(id.into());
+        w.hsk_lev = Some(hsk_lev);
+        w.into()
+    }
+    fn guid(m: &Manifest, id: &str) -> String {
+        let id = EntryId::Word(id.into());
+        m.entries.iter().find(|x| x.id == id).unwrap().guid.clone()
+    }
+
+    #[test]
+    fn rebuild() {
+        let first = Manifest::new(
+            &[word("你", 1), word("好", 1), word("人", 1)],
+            None,
+            &PackageOptions::default(),
+        )
+        .unwrap();
+        // a GUID not derived from the id, as if the derivation changed since
+        let mut first_entries = first.entries;
+        first_entries[0].guid = "kept".into();
+        let first = Manifest {
+            entries: first_entries,
+        };
+        let path =
+            std::env::temp_dir().join(format!("ankizh-manifest-{}.json", std::process::id()));
+        first.write(&path).unwrap();
+        let loaded = Manifest::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let second = Manifest::new(
+            &[word("好", 2), word("你", 1), word("大", 1)],
+            Some(&loaded),
+            &PackageOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(guid(&second, "你"), "kept");
+        assert_eq!(guid(&second, "好"), guid(&first, "好"));
+        assert_eq!(guid(&second, "大"), guid_for(EntryId::Word("大".into())));
+
+        let changes = Changes::between(&loaded, &second);
+        let id = |x: &str| EntryId::Word(x.into());
+        assert_eq!(changes.added, vec![id("大")]);
+        assert_eq!(changes.removed, vec![id("人")]);
+        assert_eq!(changes.changed, vec![id("好")]);
+        assert_eq!(changes.moved, vec![id("你")]);
+        // what --changes-only puts in the package
+        assert_eq!(changes.to_import(), HashSet::from([id("好"), id("大")]));
+    }
+
+    #[test]
+    fn hash_of_shown_content() {
+        let at_line = |line: u64| -> CommonEntry {
+            let mut w = WordEntry::from_id("好".into());
+            w.definitions = vec![Definition {
+                pinyin: Some("hao3".into()),
+                english: vec!["good".into()],
+                origin: Provenance::default(),
+            }];
+            w.stamp(&Provenance::line(line));
+            w.into()
+        };
+        let opts = PackageOptions::default();
+        assert_eq!(
+            content_hash(&at_line(1), &opts),
+            content_hash(&at_line(2), &opts)
+        );
+        let opts = PackageOptions {
+            provenance: true,
+            ..Default::default()
+        };
+        assert_ne!(
+            content_hash(&at_line(1), &opts),
+            content_hash(&at_line(2), &opts)
+        );
+    }
+
+    #[test]
+    fn guid_collision() {
+        let mut previous =
+            Manifest::new(&[word("你", 1)], None, &PackageOptions::default()).unwrap();
+        previous.entries[0].guid = guid_for(EntryId::Word("好".into()));
+        assert!(Manifest::new(
+            &[word("好", 1)],
+            Some(&previous),
+            &PackageOptions::default()
+        )
+        .is_err());
+        assert!(Manifest::new(
+            &[word("你", 1)],
+            Some(&previous),
+            &PackageOptions::default()
+        )
+        .is_ok());
+    }
+}
@@ -1,60 +1,468 @@
+use crate::error::*;
 use core::fmt;
 use pinyin::ToPinyin;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 
-#[cfg(not(test))]
-fn catch_unwind_silent<F: FnOnce() -> R + std::panic::UnwindSafe, R>(
-    f: F,
-) -> std::thread::Result<R> {
-    let prev_hook = std::panic::take_hook();
-    std::panic::set_hook(Box::new(|_| {}));
-    let result = std::panic::catch_unwind(f);
-    std::panic::set_hook(prev_hook);
-    result
+/// Tone of a syllable, the neutral tone is numbered 5
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Tone {
+    First,
+    Second,
+    Third,
+    Fourth,
+    Neutral,
 }
-#[cfg(test)]
-fn catch_unwind_silent<F: FnOnce() -> R + std::panic::UnwindSafe, R>(
-    f: F,
-) -> std::thread::Result<R> {
-    std::panic::catch_unwind(f)
-}
-fn process_pinyin(s: &str) -> String {
-    let s = s.trim();
-    let s = {
-        let mut ans = String::new();
-        let mut lastnum = false;
+impl Tone {
+    pub fn number(self) -> u8 {
+        match self {
+            Tone::First => 1,
+            Tone::Second => 2,
+            Tone::Third => 3,
+            Tone::Fourth => 4,
+            Tone::Neutral => 5,
+        }
+    }
+    /// 0 and 5 are both used for the neutral tone
+    pub fn from_number(n: u32) -> Option<Self> {
+        match n {
+            1 => Some(Tone::First),
+            2 => Some(Tone::Second),
+            3 => Some(Tone::Third),
+            4 => Some(Tone::Fourth),
+            0 | 5 => Some(Tone::Neutral),
+            _ => None,
+        }
+    }
+    /// Combining diacritic of the tone, the neutral tone has none
+    pub fn mark(self) -> Option<char> {
+        TONE_MARKS.get(self.number() as usize - 1).copied()
+    }
+}
+
+const TONE_MARKS: [char; 4] = ['\u{304}', '\u{301}', '\u{30c}', '\u{300}'];
+/// Letters that carry the tone mark, with their precomposed forms for tones 1 to 4
+const MARKED: [(char, [Option<char>; 4]); 9] = [
+    ('a', [Some('ā'), Some('á'), Some('ǎ'), Some('à')]),
+    ('e', [Some('ē'), Some('é'), Some('ě'), Some('è')]),
+    ('i', [Some('ī'), Some('í'), Some('ǐ'), Some('ì')]),
+    ('o', [Some('ō'), Some('ó'), Some('ǒ'), Some('ò')]),
+    ('u', [Some('ū'), Some('ú'), Some('ǔ'), Some('ù')]),
+    ('ü', [Some('ǖ'), Some('ǘ'), Some('ǚ'), Some('ǜ')]),
+    ('ê', [None, Some('ế'), None, Some('ề')]),
+    ('m', [None, Some('ḿ'), None, None]),
+    ('n', [None, Some('ń'), Some('ň'), Some('ǹ')]),
+];
+fn add_mark(c: char, tone: Tone, out: &mut String) {
+    let Some(mark) = tone.mark() else {
+        out.push(c);
+        return;
+    };
+    let precomposed = MARKED
+        .iter()
+        .find(|x| x.0 == c)
+        .and_then(|x| x.1[tone.number() as usize - 1]);
+    match precomposed {
+        Some(m) => out.push(m),
+        None => {
+            out.push(c);
+            out.push(mark);
+        }
+    }
+}
+fn remove_mark(c: char) -> Option<(char, Tone)> {
+    MARKED.iter().find_map(|(base, marked)| {
+        let i = marked.iter().position(|x| *x == Some(c))?;
+        Some((*base, Tone::from_number(i as u32 + 1)?))
+    })
+}
+
+pub const INITIALS: [&str; 21] = [
+    "b", "p", "m", "f", "d", "t", "n", "l", "g", "k", "h", "j", "q", "x", "zh", "ch", "sh", "r",
+    "z", "c", "s",
+];
+/// Finals in their full form, eg: "iou" is written "iu" after an initial and "you" without
+pub const FINALS: [&str; 41] = [
+    "a", "o", "e", "ê", "er", "ai", "ei", "ao", "ou", "an", "en", "ang", "eng", "ong", "i", "ia",
+    "io", "ie", "iao", "iou", "ian", "in", "iang", "ing", "iong", "u", "ua", "uo", "uai", "uei",
+    "uan", "uen", "uang", "ueng", "ü", "üe", "üan", "ün", "m", "n", "ng",
+];
+
+/// Initials each of the [`FINALS`] is found after, separated by spaces. Every final but "ong" is
+/// also found without initial
+const COMBINATIONS: [&str; 41] = [
+    "b p m f d t n l g k h zh ch sh z c s",
+    "b p m f",
+    "m d t n l g k h zh ch sh r z c s",
+    "",
+    "",
+    "b p m d t n l g k h zh ch sh z c s",
+    "b p m f d n l g h sh z",
+    "b p m d t n l g k h zh ch sh r z c s",
+    "p m f d t n l g k h zh ch sh r z c s",
+    "b p m f d t n l g k h zh ch sh r z c s",
+    "b p m f d n g k h zh ch sh r z c s",
+    "b p m f d t n l g k h zh ch sh r z c s",
+    "b p m f d t n l g k h zh ch sh r z c s",
+    "d t n l g k h zh ch r z c s",
+    "b p m d t n l j q x zh ch sh r z c s",
+    "d l j q x",
+    "",
+    "b p m d t n l j q x",
+    "b p m d t n l j q x",
+    "m d n l j q x",
+    "b p m d t n l j q x",
+    "b p m n l j q x",
+    "n l j q x",
+    "b p m d t n l j q x",
+    "j q x",
+    "b p m f d t n l g k h zh ch sh r z c s",
+    "g k h zh ch sh",
+    "d t n l g k h zh ch sh r z c s",
+    "g k h zh ch sh",
+    "d t g k h zh ch sh r z c s",
+    "d t n l g k h zh ch sh r z c s",
+    "d t l g k h zh ch sh r z c s",
+    "g k h zh ch sh",
+    "",
+    "n l j q x",
+    "n l j q x",
+    "j q x",
+    "j q x",
+    "",
+    "",
+    "",
+];
+
+/// Bopomofo of each of the [`INITIALS`]
+const ZHUYIN_INITIALS: [&str; 21] = [
+    "ㄅ", "ㄆ", "ㄇ", "ㄈ", "ㄉ", "ㄊ", "ㄋ", "ㄌ", "ㄍ", "ㄎ", "ㄏ", "ㄐ", "ㄑ", "ㄒ", "ㄓ", "ㄔ",
+    "ㄕ", "ㄖ", "ㄗ", "ㄘ", "ㄙ",
+];
+/// Bopomofo of each of the [`FINALS`]
+const ZHUYIN_FINALS: [&str; 41] = [
+    "ㄚ", "ㄛ", "ㄜ", "ㄝ", "ㄦ", "ㄞ", "ㄟ", "ㄠ", "ㄡ", "ㄢ", "ㄣ", "ㄤ", "ㄥ", "ㄨㄥ", "ㄧ",
+    "ㄧㄚ", "ㄧㄛ", "ㄧㄝ", "ㄧㄠ", "ㄧㄡ", "ㄧㄢ", "ㄧㄣ", "ㄧㄤ", "ㄧㄥ", "ㄩㄥ", "ㄨ", "ㄨㄚ",
+    "ㄨㄛ", "ㄨㄞ", "ㄨㄟ", "ㄨㄢ", "ㄨㄣ", "ㄨㄤ", "ㄨㄥ", "ㄩ", "ㄩㄝ", "ㄩㄢ", "ㄩㄣ", "ㄇ",
+    "ㄋ", "ㄫ",
+];
+/// Broad IPA of each of the [`INITIALS`]
+const IPA_INITIALS: [&str; 21] = [
+    "p", "pʰ", "m", "f", "t", "tʰ", "n", "l", "k", "kʰ", "x", "tɕ", "tɕʰ", "ɕ", "ʈʂ", "ʈʂʰ", "ʂ",
+    "ʐ", "ts", "tsʰ", "s",
+];
+/// Broad IPA of each of the [`FINALS`]
+const IPA_FINALS: [&str; 41] = [
+    "a", "o", "ɤ", "ɛ", "aɚ", "ai̯", "ei̯", "ɑu̯", "ou̯", "an", "ən", "ɑŋ", "ɤŋ", "ʊŋ", "i", "ja",
+    "jɔ", "jɛ", "jɑu̯", "jou̯", "jɛn", "in", "jɑŋ", "iŋ", "jʊŋ", "u", "wa", "wo", "wai̯", "wei̯",
+    "wan", "wən", "wɑŋ", "wɤŋ", "y", "ɥɛ", "ɥɛn", "yn", "m̩", "n̩", "ŋ̍",
+];
+/// Chao tone letters of tones 1 to 4, the neutral tone has none
+const IPA_TONES: [&str; 4] = ["˥", "˧˥", "˨˩˦", "˥˩"];
+
+/// Restores the full form of a final written after an initial
+fn full_final(rest: &str) -> String {
+    match rest {
+        "iu" => "iou".into(),
+        "ui" => "uei".into(),
+        "un" => "uen".into(),
+        "ue" => "üe".into(),
+        _ => rest.into(),
+    }
+}
+
+/// How a syllable was written, eg: Zhāng for a name, BĚI in an all caps title
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum Case {
+    #[default]
+    Lower,
+    Capitalized,
+    Upper,
+}
+impl Case {
+    /// Case of a written syllable, a single capital letter counts as capitalized
+    fn of(s: &str) -> Self {
+        let mut letters = s.chars().filter(|x| x.is_alphabetic());
+        match letters.next() {
+            Some(c) if c.is_uppercase() => {
+                let rest: Vec<char> = letters.collect();
+                if !rest.is_empty() && rest.iter().all(|x| x.is_uppercase()) {
+                    Case::Upper
+                } else {
+                    Case::Capitalized
+                }
+            }
+            _ => Case::Lower,
+        }
+    }
+    fn apply(self, s: &str) -> String {
+        match self {
+            Case::Lower => s.to_owned(),
+            Case::Upper => s.to_uppercase(),
+            Case::Capitalized => {
+                let mut chars = s.chars();
+                chars
+                    .next()
+                    .map(|c| c.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// A syllable, ordered by initial, final then tone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Syllable {
+    /// Index in [`INITIALS`], none for syllables starting with a vowel
+    initial: Option<u8>,
+    /// Index in [`FINALS`]
+    final_: u8,
+    pub tone: Tone,
+    /// Ends with a rhotic r, eg: nǎr
+    pub erhua: bool,
+    /// Restored when written as pinyin, ignored by zhuyin and IPA
+    pub case: Case,
+}
+impl Syllable {
+    /// `final_` is in its full form, see [`FINALS`].
+    /// "er" has no initial, the r after an initial is erhua, eg: zhèr is zhè with erhua
+    /// Only syllables of [`COMBINATIONS`] are made, eg: not gi nor bü
+    pub fn new(initial: Option<&str>, final_: &str, tone: Tone, erhua: bool) -> Option<Self> {
+        let f = FINALS.iter().position(|x| *x == final_)?;
+        let valid = match initial {
+            Some(i) => COMBINATIONS[f].split(' ').any(|x| x == i),
+            None => final_ != "ong",
+        };
+        if !valid {
+            return None;
+        }
+        let initial = match initial {
+            Some(i) => Some(INITIALS.iter().position(|x| *x == i)? as u8),
+            None => None,
+        };
+        Some(Self {
+            initial,
+            final_: f as u8,
+            tone,
+            erhua,
+            case: Case::Lower,
+        })
+    }
+    /// Characters the syllable is written with when its erhua is written, eg: 2 for 哪儿
+    pub fn written_chars(&self) -> usize {
+        1 + self.erhua as usize
+    }
+    pub fn initial(&self) -> Option<&'static str> {
+        self.initial.map(|x| INITIALS[x as usize])
+    }
+    pub fn final_(&self) -> &'static str {
+        FINALS[self.final_ as usize]
+    }
+    /// Reads a single syllable, with tone marks (nǎr) or a tone number (nar3, lv4, lu:4).
+    /// Syllables without tone are neutral
+    pub fn parse(s: &str) -> Option<Self> {
+        let case = Case::of(s);
+        let s = s.trim().to_lowercase().replace("u:", "ü").replace('v', "ü");
+        let (s, mut tone) = match s.chars().last().and_then(|x| x.to_digit(10)) {
+            Some(n) => (&s[..s.len() - 1], Some(Tone::from_number(n)?)),
+            None => (s.as_str(), None),
+        };
+        let mut plain = String::new();
         for c in s.chars() {
-            if c.is_alphabetic() && lastnum {
-                ans.push(' ');
+            let (base, t) = match remove_mark(c) {
+                Some((base, t)) => (Some(base), Some(t)),
+                None => match TONE_MARKS.iter().position(|x| *x == c) {
+                    Some(i) => (None, Tone::from_number(i as u32 + 1)),
+                    None => (Some(c), None),
+                },
+            };
+            if let Some(t) = t {
+                if tone.replace(t).is_some() {
+                    return None;
+                }
             }
-            ans.push(c);
-            lastnum = c.is_numeric();
+            plain.extend(base);
         }
-        ans
-    };
-    let s = prettify_pinyin::prettify(&s);
-    let s = s.trim();
-    catch_unwind_silent(|| {
-        let parser = pinyin_parser::PinyinParser::new()
-            .preserve_spaces(true)
-            .preserve_punctuations(true)
-            .with_strictness(pinyin_parser::Strictness::Loose)
-            .preserve_miscellaneous(true);
-        parser.parse(s).fold(String::new(), |acc, s| {
-            let st = s.trim();
-            let sep = if acc.is_empty() || st.is_empty() {
-                ""
-            } else {
-                " "
+        if !plain.chars().all(|x| x.is_alphabetic()) {
+            return None;
+        }
+        let tone = tone.unwrap_or(Tone::Neutral);
+        let syllable = Self::from_spelling(&plain, tone, false).or_else(|| {
+            plain
+                .strip_suffix('r')
+                .and_then(|x| Self::from_spelling(x, tone, true))
+        })?;
+        Some(Self { case, ..syllable })
+    }
+    fn from_spelling(s: &str, tone: Tone, erhua: bool) -> Option<Self> {
+        let split = ["zh", "ch", "sh"]
+            .into_iter()
+            .chain(INITIALS)
+            .chain(["y", "w"])
+            .find_map(|i| s.strip_prefix(i).map(|rest| (i, rest)));
+        if let Some((initial, rest)) = split {
+            let final_ = match initial {
+                "y" => match rest {
+                    "i" | "in" | "ing" => rest.to_owned(),
+                    _ if rest.starts_with(['u', 'ü']) => {
+                        format!("ü{}", &rest[rest.chars().next()?.len_utf8()..])
+                    }
+                    _ => format!("i{}", rest),
+                },
+                "w" => match rest {
+                    "u" => rest.to_owned(),
+                    _ => format!("u{}", rest),
+                },
+                "j" | "q" | "x" if rest.starts_with('u') => format!("ü{}", &rest[1..]),
+                _ => full_final(rest),
             };
-            acc + sep + st
-        })
-    })
-    .unwrap_or(s.to_string())
+            let initial = (!matches!(initial, "y" | "w")).then_some(initial);
+            if let Some(syllable) = Self::new(initial, &final_, tone, erhua) {
+                return Some(syllable);
+            }
+        }
+        // syllables without initial are written with y or w when they start with i, u or ü
+        if s.starts_with(['i', 'u', 'ü']) {
+            return None;
+        }
+        Self::new(None, s, tone, erhua)
+    }
+    /// Written form without tone nor erhua, eg: "you" for the final "iou"
+    fn spelling(&self) -> String {
+        let f = self.final_();
+        match self.initial() {
+            None => match f {
+                "i" | "in" | "ing" => format!("y{}", f),
+                "u" => "wu".into(),
+                _ if f.starts_with('i') => format!("y{}", &f[1..]),
+                _ if f.starts_with('u') => format!("w{}", &f[1..]),
+                _ if f.starts_with('ü') => format!("yu{}", &f['ü'.len_utf8()..]),
+                _ => f.into(),
+            },
+            Some(i) => {
+                let f = match f {
+                    "iou" => "iu",
+                    "uei" => "ui",
+                    "uen" => "un",
+                    _ => f,
+                };
+                if matches!(i, "j" | "q" | "x") && f.starts_with('ü') {
+                    format!("{}u{}", i, &f['ü'.len_utf8()..])
+                } else {
+                    format!("{}{}", i, f)
+                }
+            }
+        }
+    }
+    /// With a tone number, ü is written v, eg: "lv4", "nar3"
+    pub fn to_numbers(&self) -> String {
+        let s = format!(
+            "{}{}{}",
+            self.spelling().replace('ü', "v"),
+            if self.erhua { "r" } else { "" },
+            self.tone.number()
+        );
+        self.case.apply(&s)
+    }
+    /// The "i" of zhi, chi, shi, ri, zi, ci and si, which is not the vowel of "ji"
+    fn is_apical(&self) -> bool {
+        self.final_() == "i"
+            && matches!(
+                self.initial(),
+                Some("zh" | "ch" | "sh" | "r" | "z" | "c" | "s")
+            )
+    }
+    /// In bopomofo, eg: "ㄋㄚˇㄦ", the neutral tone is a dot before the syllable
+    pub fn to_zhuyin(&self) -> String {
+        let mut s = String::new();
+        if self.tone == Tone::Neutral {
+            s.push('˙');
+        }
+        s.extend(self.initial.map(|x| ZHUYIN_INITIALS[x as usize]));
+        if !self.is_apical() {
+            s.push_str(ZHUYIN_FINALS[self.final_ as usize]);
+        }
+        s.push_str(match self.tone {
+            Tone::Second => "ˊ",
+            Tone::Third => "ˇ",
+            Tone::Fourth => "ˋ",
+            Tone::First | Tone::Neutral => "",
+        });
+        if self.erhua {
+            s.push('ㄦ');
+        }
+        s
+    }
+    /// In broad IPA with Chao tone letters, eg: "na˞˨˩˦"
+    pub fn to_ipa(&self) -> String {
+        let mut s: String = self
+            .initial
+            .map(|x| IPA_INITIALS[x as usize])
+            .unwrap_or_default()
+            .into();
+        s.push_str(match self.initial() {
+            Some("zh" | "ch" | "sh" | "r") if self.is_apical() => "ʐ̩",
+            Some(_) if self.is_apical() => "z̩",
+            _ => IPA_FINALS[self.final_ as usize],
+        });
+        if self.erhua {
+            s.push('˞');
+        }
+        if let Some(t) = IPA_TONES.get(self.tone.number() as usize - 1) {
+            s.push_str(t);
+        }
+        s
+    }
+}
+/// Which letter of a spelling carries the tone mark
+fn mark_position(s: &str) -> usize {
+    let chars: Vec<char> = s.chars().collect();
+    let find = |set: &[char]| chars.iter().position(|c| set.contains(c));
+    find(&['a', 'e', 'ê'])
+        .or_else(|| s.contains("ou").then(|| find(&['o'])).flatten())
+        .or_else(|| chars.iter().rposition(|c| "iouü".contains(*c)))
+        .or_else(|| find(&['m', 'n']))
+        .unwrap_or(0)
+}
+/// With tone marks, eg: "nǎr"
+impl fmt::Display for Syllable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let spelling = self.spelling();
+        let at = mark_position(&spelling);
+        let mut s = String::new();
+        for (i, c) in spelling.chars().enumerate() {
+            if i == at {
+                add_mark(c, self.tone, &mut s);
+            } else {
+                s.push(c);
+            }
+        }
+        if self.erhua {
+            s.push('r');
+        }
+        write!(f, "{}", self.case.apply(&s))
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
-pub struct Pinyin(String);
+/// A syllable, or anything else found in pinyin (punctuation, latin words...), kept as is
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum PinyinPart {
+    Syllable(Syllable),
+    Other(String),
+}
+impl fmt::Display for PinyinPart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PinyinPart::Syllable(s) => write!(f, "{}", s),
+            PinyinPart::Other(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Default)]
+pub struct Pinyin(Vec<PinyinPart>);
 impl Pinyin {
     /// Use sparingly, it's not very accurate
     pub fn from_hanzi<S: AsRef<str>>(s: S) -> Self {
@@ -66,77 +474,364 @@ impl Pinyin {
             .fold(String::new(), |acc, e| acc + " " + &e);
         Self::from(spy)
     }
+    pub fn from_parts(parts: impl IntoIterator<Item = PinyinPart>) -> Self {
+        Self(parts.into_iter().collect())
+    }
+    pub fn from_syllables(syllables: impl IntoIterator<Item = Syllable>) -> Self {
+        Self::from_parts(syllables.into_iter().map(PinyinPart::Syllable))
+    }
+    pub fn parts(&self) -> &[PinyinPart] {
+        &self.0
+    }
+    pub fn syllables(&self) -> impl Iterator<Item = &Syllable> {
+        self.0.iter().filter_map(|x| match x {
+            PinyinPart::Syllable(s) => Some(s),
+            PinyinPart::Other(_) => None,
+        })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Same reading with every syllable in lower case, other words are kept as is
+    pub fn to_lowercase(&self) -> Self {
+        Self::from_parts(self.0.iter().map(|x| match x {
+            PinyinPart::Syllable(s) => PinyinPart::Syllable(Syllable {
+                case: Case::Lower,
+                ..*s
+            }),
+            other => other.clone(),
+        }))
+    }
+    /// Whether some words could not be read as pinyin, see [`parse_pinyin`]
+    pub fn has_unreadable(&self) -> bool {
+        self.0
+            .iter()
+            .any(|x| matches!(x, PinyinPart::Other(s) if is_unreadable(s)))
+    }
+    /// With tone numbers, eg: "ni3 hao3"
+    pub fn to_numbers(&self) -> String {
+        self.join(|x| match x {
+            PinyinPart::Syllable(s) => s.to_numbers(),
+            PinyinPart::Other(s) => s.clone(),
+        })
+    }
+    /// In bopomofo, eg: "ㄋㄧˇ ㄏㄠˇ"
+    pub fn to_zhuyin(&self) -> String {
+        self.join(|x| match x {
+            PinyinPart::Syllable(s) => s.to_zhuyin(),
+            PinyinPart::Other(s) => s.clone(),
+        })
+    }
+    /// In broad IPA, eg: "ni˨˩˦ xɑu̯˨˩˦"
+    pub fn to_ipa(&self) -> String {
+        self.join(|x| match x {
+            PinyinPart::Syllable(s) => s.to_ipa(),
+            PinyinPart::Other(s) => s.clone(),
+        })
+    }
+    /// Parts written with `f`, separated by spaces except before closing punctuation and after
+    /// opening punctuation
+    pub fn join(&self, f: impl Fn(&PinyinPart) -> String) -> String {
+        let mut s = String::new();
+        let mut opening = false;
+        for part in self.0.iter() {
+            let closing = matches!(part, PinyinPart::Other(x) if x.starts_with(CLOSING));
+            if !s.is_empty() && !closing && !opening {
+                s.push(' ');
+            }
+            s.push_str(&f(part));
+            opening = matches!(part, PinyinPart::Other(x) if x.ends_with(OPENING));
+        }
+        s
+    }
 }
+/// With tone marks, syllables separated by spaces
 impl fmt::Display for Pinyin {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.join(|x| x.to_string()))
     }
 }
+/// Unparseable parts are kept, see [`parse_pinyin`]
 impl<S: AsRef<str>> From<S> for Pinyin {
     fn from(s: S) -> Self {
-        Self(process_pinyin(s.as_ref()))
+        parse_pinyin(s.as_ref()).0
+    }
+}
+
+/// Punctuation written right after the previous syllable
+const CLOSING: [char; 20] = [
+    ',', '.', '!', '?', ';', ':', ')', ']', '，', '。', '！', '？', '；', '：', '）', '”', '》',
+    '」', '』', '、',
+];
+/// Punctuation written right before the next syllable
+const OPENING: [char; 7] = ['(', '[', '（', '“', '《', '「', '『'];
+/// Separate syllables without being kept, eg: xī'ān
+const SEPARATORS: [char; 3] = ['\'', '’', '-'];
+/// Longest syllable, in chars: zhuang, erhua r, a combining tone mark and a tone number
+const MAX_SYLLABLE: usize = 9;
+
+/// Letters that could not be read as pinyin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Byte offset in the input
+    pub position: usize,
+    pub text: String,
+}
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "not pinyin at {}: {}", self.position, self.text)
+    }
+}
+
+/// Words in capitals are not pinyin on purpose
+fn is_unreadable(word: &str) -> bool {
+    word.chars().any(|x| x.is_lowercase())
+}
+
+/// Reads the pinyin of a record of `file`, warning in `report` about what is not pinyin
+pub fn read_pinyin(s: &str, report: &ErrorReport, file: &Path, line: Option<u64>) -> Pinyin {
+    let (py, diagnostics) = parse_pinyin(s);
+    for d in diagnostics {
+        report.warn(Error::parse(format!("{} in \"{}\"", d, s)), file, line);
+    }
+    py
+}
+
+/// Reads pinyin written with tone marks or tone numbers, with or without spaces between
+/// syllables.
+/// Anything else is kept as is: punctuation, numbers and words in capitals (eg: the "O K" of
+/// CEDICT's "Ka3 la1 O K") silently, other words that are not pinyin with a diagnostic
+pub fn parse_pinyin(s: &str) -> (Pinyin, Vec<Diagnostic>) {
+    let mut parts = vec![];
+    let mut diagnostics = vec![];
+    let chars: Vec<(usize, char)> = s.char_indices().collect();
+    let offset = |i: usize| chars.get(i).map(|x| x.0).unwrap_or(s.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i].1;
+        let start = i;
+        if c.is_whitespace() || SEPARATORS.contains(&c) {
+            i += 1;
+        } else if c.is_alphabetic() {
+            // letters, with their tone marks or numbers and the : of u:
+            while i < chars.len() {
+                let c = chars[i].1;
+                let umlaut = c == ':' && matches!(chars[i - 1].1, 'u' | 'U');
+                if !(c.is_alphabetic() || TONE_MARKS.contains(&c) || c.is_ascii_digit() || umlaut) {
+                    break;
+                }
+                i += 1;
+            }
+            let word = &s[offset(start)..offset(i)];
+            match parse_word(word) {
+                Some(syllables) => parts.extend(syllables.into_iter().map(PinyinPart::Syllable)),
+                None => {
+                    // CEDICT writes erhua as a syllable of its own, eg: "na3 r5"
+                    if let (true, Some(PinyinPart::Syllable(prev))) =
+                        (matches!(word, "r" | "r5"), parts.last_mut())
+                    {
+                        if !prev.erhua {
+                            prev.erhua = true;
+                            continue;
+                        }
+                    }
+                    if is_unreadable(word) {
+                        diagnostics.push(Diagnostic {
+                            position: offset(start),
+                            text: word.to_owned(),
+                        });
+                    }
+                    parts.push(PinyinPart::Other(word.to_owned()));
+                }
+            }
+        } else {
+            while i < chars.len() {
+                let c = chars[i].1;
+                if c.is_whitespace() || c.is_alphabetic() || SEPARATORS.contains(&c) {
+                    break;
+                }
+                i += 1;
+            }
+            parts.push(PinyinPart::Other(s[offset(start)..offset(i)].to_owned()));
+        }
+    }
+    (Pinyin(parts), diagnostics)
+}
+
+/// Splits a word in syllables.
+/// Syllables starting with a vowel are written after an apostrophe, so splits that need fewer of
+/// them are preferred (eg: "fangan" is fan gan, fāng àn is written "fang'an"), then splits with
+/// fewer syllables (eg: "xian" is a single syllable)
+fn parse_word(word: &str) -> Option<Vec<Syllable>> {
+    // words in capitals without tones are latin text
+    if word.chars().all(|x| x.is_uppercase()) {
+        return None;
+    }
+    let w: Vec<char> = word.chars().collect();
+    let n = w.len();
+    // for each position, the cost of the best split of what follows, and the first syllable of
+    // that split with where it ends
+    let mut costs: Vec<Option<(usize, usize)>> = vec![None; n + 1];
+    let mut first: Vec<Option<(usize, Syllable)>> = vec![None; n + 1];
+    costs[n] = Some((0, 0));
+    for i in (0..n).rev() {
+        let c = w[i].to_lowercase().next().unwrap_or(w[i]);
+        let vowel = remove_mark(c).map(|x| x.0).unwrap_or(c);
+        let penalty = usize::from(i > 0 && matches!(vowel, 'a' | 'e' | 'o' | 'ê'));
+        for j in i + 1..=n.min(i + MAX_SYLLABLE) {
+            // a tone number belongs to the syllable before it
+            if j < n && w[j].is_ascii_digit() {
+                continue;
+            }
+            let Some((vowels, count)) = costs[j] else {
+                continue;
+            };
+            let candidate: String = w[i..j].iter().collect();
+            let Some(syllable) = Syllable::parse(&candidate) else {
+                continue;
+            };
+            let cost = (vowels + penalty, count + 1);
+            if costs[i].is_none_or(|x| cost < x) {
+                costs[i] = Some(cost);
+                first[i] = Some((j, syllable));
+            }
+        }
     }
+    let mut syllables = vec![];
+    let mut i = 0;
+    while i < n {
+        let (j, syllable) = first[i]?;
+        syllables.push(syllable);
+        i = j;
+    }
+    Some(syllables)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+/// Pinyin that may be capitalized, as CEDICT does for proper nouns
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Default)]
 pub struct CapPinyin {
-    py: String,
-    cap: bool,
+    py: Pinyin,
 }
 impl CapPinyin {
     pub fn is_capitalized(&self) -> bool {
-        self.cap
+        self.py
+            .syllables()
+            .next()
+            .is_some_and(|x| x.case != Case::Lower)
+    }
+    /// The reading without capitals, as found in [`crate::common::WordEntry::pinyin`]
+    pub fn pinyin(&self) -> Pinyin {
+        self.py.to_lowercase()
+    }
+    /// The reading with its capitals
+    pub fn written(&self) -> &Pinyin {
+        &self.py
     }
 }
 impl From<Pinyin> for CapPinyin {
     fn from(p: Pinyin) -> Self {
-        Self {
-            py: p.0,
-            cap: false,
-        }
+        Self { py: p }
     }
 }
 impl<S: AsRef<str>> From<S> for CapPinyin {
     fn from(s: S) -> Self {
         Self {
-            py: process_pinyin(s.as_ref()),
-            cap: s
-                .as_ref()
-                .chars()
-                .next()
-                .map(|x| x.is_uppercase())
-                .unwrap_or(false),
+            py: Pinyin::from(s.as_ref()),
         }
     }
 }
 impl fmt::Display for CapPinyin {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.cap {
-            write!(
-                f,
-                "{}{}",
-                self.py.chars().next().unwrap().to_uppercase(),
-                self.py.chars().skip(1).collect::<String>()
-            )
-        } else {
-            write!(f, "{}", self.py)
-        }
+        write!(f, "{}", self.py)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    fn test_pyfrom(a: &str, b: &str) {
-        assert_eq!(Pinyin::from(&a).to_string(), b.to_string());
+    #[test]
+    fn syllables() {
+        for (marks, numbers) in [
+            ("nǐ", "ni3"),
+            ("lǜ", "lv4"),
+            ("jué", "jue2"),
+            ("yǒu", "you3"),
+            ("wèi", "wei4"),
+            ("yuán", "yuan2"),
+            ("guǐ", "gui3"),
+            ("nǎr", "nar3"),
+            ("èr", "er4"),
+            ("de", "de5"),
+        ] {
+            let a = Syllable::parse(marks).unwrap();
+            let b = Syllable::parse(numbers).unwrap();
+            assert_eq!(a, b);
+            assert_eq!(a.to_string(), marks);
+            assert_eq!(a.to_numbers(), numbers);
+        }
+        for zher in ["zhèr", "zher4"] {
+            let s = Syllable::parse(zher).unwrap();
+            assert_eq!((s.initial(), s.final_(), s.erhua), (Some("zh"), "e", true));
+        }
+        let s = Syllable::parse("jiu3").unwrap();
+        assert_eq!((s.initial(), s.final_()), (Some("j"), "iou"));
+        assert_eq!(Syllable::parse("lu:4"), Syllable::parse("lǜ"));
+        assert!(Syllable::parse("english").is_none());
+        assert!(Syllable::parse("ni9").is_none());
+    }
+    /// Each line of the corpus is the input, then how it is written with tone marks and with
+    /// tone numbers, separated by tabs
+    #[test]
+    fn corpus() {
+        for line in include_str!("pinyin_corpus.tsv").lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let cols: Vec<&str> = line.split('\t').collect();
+            let (py, diagnostics) = parse_pinyin(cols[0]);
+            assert!(diagnostics.is_empty(), "{:?}: {:?}", cols[0], diagnostics);
+            assert_eq!(py.to_string(), cols[1], "{:?}", cols[0]);
+            assert_eq!(py.to_numbers(), cols[2], "{:?}", cols[0]);
+            assert_eq!(Pinyin::from(cols[2]), py, "{:?}", cols[2]);
+        }
+    }
+    #[test]
+    fn diagnostics() {
+        let (py, diagnostics) = parse_pinyin("ni3 english xx5");
+        assert_eq!(py.to_string(), "nǐ english xx5");
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|x| (x.position, x.text.as_str()))
+            .collect();
+        assert_eq!(found, vec![(4, "english"), (12, "xx5")]);
+        assert_eq!(parse_pinyin("hao9").1.len(), 1);
+        assert!(parse_pinyin("Ka3 la1 O K").1.is_empty());
+        for invalid in ["gi1", "bü4", "hm5", "fiao3"] {
+            assert_eq!(parse_pinyin(invalid).1.len(), 1, "{}", invalid);
+        }
+    }
+    #[test]
+    fn renderings() {
+        for (py, zhuyin, ipa) in [
+            ("ni3 hao3", "ㄋㄧˇ ㄏㄠˇ", "ni˨˩˦ xɑu̯˨˩˦"),
+            ("zhi1 shi5", "ㄓ ˙ㄕ", "ʈʂʐ̩˥ ʂʐ̩"),
+            ("si4 lü4", "ㄙˋ ㄌㄩˋ", "sz̩˥˩ ly˥˩"),
+            ("xue2 you3", "ㄒㄩㄝˊ ㄧㄡˇ", "ɕɥɛ˧˥ jou̯˨˩˦"),
+            ("na3 r5", "ㄋㄚˇㄦ", "na˞˨˩˦"),
+            ("zher4", "ㄓㄜˋㄦ", "ʈʂɤ˞˥˩"),
+            ("hao3, ma5?", "ㄏㄠˇ, ˙ㄇㄚ?", "xɑu̯˨˩˦, ma?"),
+        ] {
+            let py = Pinyin::from(py);
+            assert_eq!(py.to_zhuyin(), zhuyin);
+            assert_eq!(py.to_ipa(), ipa);
+        }
     }
     #[test]
-    fn py() {
-        test_pyfrom("nǐér", "nǐ ér");
-        test_pyfrom("wo3bu2zhi1dao", "wǒ bú zhī dao english");
-        test_pyfrom("wo3bu2zhi1dao5", "wǒ bú zhī dao english");
-        test_pyfrom("wo3 bu2 zhi1 dao", "wǒ bú zhī dao english");
-        test_pyfrom("wǒ bú zhī dao", "wǒ bú zhī dao english");
-        test_pyfrom("wǒbúzhīdao", "wǒ bú zhī dao english");
+    fn capitalized() {
+        let py = CapPinyin::from("Zhang1 San1");
+        assert!(py.is_capitalized());
+        assert_eq!(py.to_string(), "Zhāng Sān");
+        assert_eq!(py.pinyin(), Pinyin::from("zhāng sān"));
+        assert!(!CapPinyin::from("zhang1 San1").is_capitalized());
     }
 }
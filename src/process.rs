@@ -0,0 +1,439 @@
+use crate::cache::SourceCache;
+use crate::common::*;
+use crate::config::*;
+use crate::error::*;
+use crate::graph::*;
+use crate::pinyin_type::*;
+use crate::reading::Reader;
+use crate::scoring::*;
+use crate::sources::*;
+use crate::tatoeba;
+use crate::utils::*;
+use ordered_float::NotNan;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct ProcessOptions {
+    /// Maximum number of entries in the deck
+    pub max_entries: usize,
+    /// Entries with a lower priority are not included
+    pub min_priority: f32,
+    /// Where parsed sources are cached, see [`SourceCache`]
+    pub cache_dir: Option<PathBuf>,
+    /// How cycles in the dependencies are broken
+    pub cycle_policy: CyclePolicy,
+    /// Computes the priority of the entries
+    pub scorer: Arc<dyn Scorer>,
+    /// Leave out the words whose definitions are all proper nouns, unless they are in an HSK list
+    pub drop_proper_nouns: bool,
+    pub readings: ReadingProfile,
+}
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            max_entries: 20000,
+            min_priority: 0.19f32,
+            cache_dir: None,
+            cycle_policy: CyclePolicy::default(),
+            scorer: Arc::new(WeightedScorer::default()),
+            drop_proper_nouns: false,
+            readings: ReadingProfile::default(),
+        }
+    }
+}
+
+/// Which readings become the main one of words
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadingProfile {
+    #[default]
+    Mainland,
+    /// Taiwan pronunciations come first when a source has them
+    Taiwan,
+}
+impl std::str::FromStr for ReadingProfile {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mainland" => Ok(Self::Mainland),
+            "taiwan" => Ok(Self::Taiwan),
+            _ => Err(Error::parse(format!(
+                "unknown reading profile {}, expected mainland or taiwan",
+                s
+            ))),
+        }
+    }
+}
+impl std::fmt::Display for ReadingProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Mainland => write!(f, "mainland"),
+            Self::Taiwan => write!(f, "taiwan"),
+        }
+    }
+}
+
+/// Merges entries with the same id, the ones coming first take precedence.
+/// Entries are split in shards by id so the shards can be merged in parallel.
+fn merge(sources: Vec<Vec<CommonEntry>>) -> HashMap<EntryId, CommonEntry> {
+    const SHARDS: usize = 64;
+    let mut shards: Vec<Vec<CommonEntry>> = (0..SHARDS).map(|_| vec![]).collect();
+    for e in sources.into_iter().flatten() {
+        let mut h = std::hash::DefaultHasher::new();
+        e.id().hash(&mut h);
+        shards[h.finish() as usize % SHARDS].push(e);
+    }
+    shards
+        .into_par_iter()
+        .map(|shard| {
+            let mut hm = HashMap::<EntryId, CommonEntry>::new();
+            for e in shard {
+                if let Some(hme) = hm.get_mut(&e.id()) {
+                    hme.merge(e);
+                } else {
+                    hm.insert(e.id(), e);
+                }
+            }
+            hm
+        })
+        .reduce(HashMap::new, |mut a, b| {
+            a.extend(b);
+            a
+        })
+}
+
+/// Loads all the enabled sources and merges them, filling in what is missing where possible.
+/// Entries that should not be in the deck are removed.
+/// Also returns which sources introduced each dependency
+pub fn load_entries(
+    registry: &SourceRegistry,
+    config: &Config,
+    args: &ProcessOptions,
+    report: &ErrorReport,
+) -> Result<(HashMap<EntryId, CommonEntry>, EdgeOrigins)> {
+    let cache = args.cache_dir.as_ref().map(SourceCache::new);
+    // entries of a source, with the dependencies it introduced
+    type Loaded = (Vec<CommonEntry>, Vec<(EntryId, EntryId)>, EdgeOrigin);
+    // sources are loaded concurrently, but merged in order
+    let loaded: Vec<Loaded> = config
+        .merge_order(registry)
+        .into_par_iter()
+        .map(|source| {
+            let path = config.path(source);
+            let mut entries = match cache {
+                Some(ref cache) => cache.load(source, &path, report)?,
+                None => source.load(&path, report)?,
+            };
+            let origin = Provenance::source(source.name());
+            entries.par_iter_mut().for_each(|e| e.stamp(&origin));
+            let edges = entries
+                .par_iter()
+                .flat_map_iter(|e| {
+                    let id = e.id();
+                    e.dependencies().into_iter().map(move |d| (id.clone(), d))
+                })
+                .collect();
+            let origin = EdgeOrigin {
+                source: source.name().to_owned(),
+                precedence: config.precedence(source),
+            };
+            Ok((entries, edges, origin))
+        })
+        .collect::<Result<_>>()?;
+    let mut origins = EdgeOrigins::new();
+    let mut sources = vec![];
+    for (entries, edges, origin) in loaded {
+        for edge in edges {
+            origins.entry(edge).or_default().push(origin.clone());
+        }
+        sources.push(entries);
+    }
+    let mut hm = merge(sources);
+
+    {
+        // add writings
+        let writings: Vec<(EntryId, Vec<CharWriting>)> = hm
+            .par_iter()
+            .filter_map(|(k, _)| match k {
+                EntryId::Word(w) if w.chars().count() > 1 => Some((k.clone(), w)),
+                _ => None,
+            })
+            .map(|(key, w)| {
+                let wr = w
+                    .chars()
+                    .map(|c| {
+                        hm.get(&EntryId::Word(c.into()))
+                            .map(|x| {
+                                if let CommonEntry::WordEntry(w) = x {
+                                    w.writing[0].clone()
+                                } else {
+                                    unreachable!()
+                                }
+                            })
+                            .unwrap_or(CharWriting::Char(c))
+                    })
+                    .collect();
+                (key, wr)
+            })
+            .collect();
+        for (key, wr) in writings {
+            if let CommonEntry::WordEntry(w) = hm.get_mut(&key).unwrap() {
+                w.writing = wr;
+            }
+        }
+    }
+    {
+        // variants without definitions get the ones of what they are a variant of
+        let inherited: Vec<(EntryId, Vec<Definition>)> = hm
+            .par_iter()
+            .filter_map(|(k, e)| match e {
+                CommonEntry::WordEntry(w) if w.definitions.is_empty() => Some((k, w)),
+                _ => None,
+            })
+            .filter_map(|(k, w)| {
+                let defs: Vec<Definition> = w
+                    .relations
+                    .iter()
+                    .filter(|r| r.kind == RelationKind::VariantOf)
+                    .filter_map(|r| match hm.get(&EntryId::Word(r.word.clone())) {
+                        Some(CommonEntry::WordEntry(v)) => Some(v.definitions.clone()),
+                        _ => None,
+                    })
+                    .flatten()
+                    .collect();
+                (!defs.is_empty()).then(|| (k.clone(), defs))
+            })
+            .collect();
+        for (key, defs) in inherited {
+            if let CommonEntry::WordEntry(w) = hm.get_mut(&key).unwrap() {
+                w.definitions = defs;
+                w.flags.push(Flag::VariantDefinition);
+            }
+        }
+    }
+    // add definitions to some single-character entries from unicode names
+    // and pinyin to words missing them
+    hm.par_iter_mut().for_each(|(_, entry)| {
+        if let CommonEntry::WordEntry(w) = entry {
+            w.prefer_pos_sense();
+            if args.readings == ReadingProfile::Taiwan {
+                w.prefer_region("Taiwan");
+            }
+            w.fall_back_on_regional();
+            if w.pinyin.is_empty() {
+                w.pinyin.push(Pinyin::from_hanzi(&w.id));
+                w.flags.push(Flag::GuessedPinyin);
+            }
+            if w.id.chars().count() == 1 && w.definitions.is_empty() {
+                let c = w.id.chars().next().unwrap();
+                let name = unicode_names2::name(c).unwrap().to_string();
+                if !name.contains(&format!("{:X}", c as u32)) {
+                    w.definitions.push(Definition {
+                        pinyin: None,
+                        english: vec![name],
+                        origin: Provenance::source("unicode-name"),
+                    });
+                    w.flags.push(Flag::UnicodeNameDefinition);
+                }
+            }
+        }
+    });
+    hm.retain(|_k, v| !v.to_delete());
+    if args.drop_proper_nouns {
+        hm.retain(|_k, v| match v {
+            CommonEntry::WordEntry(w) => w.hsk_lev.is_some() || !w.is_only_proper_noun(),
+            _ => true,
+        });
+    }
+    // only keep references to entries of the deck
+    let ids: HashSet<EntryId> = hm.keys().cloned().collect();
+    hm.par_iter_mut().for_each(|(_, entry)| {
+        if let CommonEntry::WordEntry(w) = entry {
+            w.relations
+                .retain(|r| r.word != w.id && ids.contains(&EntryId::Word(r.word.clone())));
+        }
+    });
+    hm.par_iter_mut()
+        .for_each(|(_, entry)| score_entry(args.scorer.as_ref(), entry));
+    Ok((hm, origins))
+}
+
+/// Where an entry ended up in the deck and why
+#[derive(Clone, Debug)]
+pub struct Placement {
+    pub position: usize,
+    /// Entries waiting for this one to be placed, from the one that started the chain to the
+    /// one directly depending on it.
+    /// Empty if the entry was placed because of its own priority
+    pub pulled_by: Vec<EntryId>,
+}
+
+/// Puts the entries in the order they should be studied in: by priority, but with each entry
+/// coming after its dependencies.
+/// The graph must not have cycles, see [`DependencyGraph::break_cycles`]
+pub fn order_entries(
+    hm: &HashMap<EntryId, CommonEntry>,
+    graph: &DependencyGraph,
+    args: &ProcessOptions,
+) -> (Vec<CommonEntry>, HashMap<EntryId, Placement>) {
+    let mut placements = HashMap::<EntryId, Placement>::new();
+    let mut ordered: Vec<(NotNan<f32>, EntryId)> = hm
+        .iter()
+        .map(|(k, v)| (v.priority(), k.clone()))
+        .filter(|(p, _k)| *p >= NotNan::new(args.min_priority).unwrap())
+        .collect();
+    ordered.sort_by_key(|e| e.0);
+    ordered = ordered
+        .into_iter()
+        .rev()
+        .take(args.max_entries)
+        .rev()
+        .collect();
+    if ordered.is_empty() {
+        return (vec![], placements);
+    }
+
+    let mut ans = vec![];
+    let mut done = HashSet::<EntryId>::new();
+    let mut stack: Vec<Vec<EntryId>> = vec![ordered.into_iter().map(|(_p, k)| k).collect()];
+    let mut ancestors = vec![];
+    ancestors.push(stack[0].last().unwrap().clone());
+    while !stack.is_empty() && ans.len() < args.max_entries {
+        while stack.last().is_some_and(|x| x.is_empty()) {
+            stack.pop();
+        }
+        //eprintln!("{:?}", &stack[1..]);
+        if let Some(lv) = stack.last_mut() {
+            if let Some(eid) = lv.last().cloned() {
+                let e = hm.get(&eid).unwrap().clone();
+                if !done.contains(&e.id()) {
+                    let mut deps: Vec<EntryId> = graph
+                        .dependencies(&eid)
+                        .filter(|x| !done.contains(*x))
+                        .cloned()
+                        .collect();
+                    // cycles should be broken beforehand, ignore the edges of any left so the
+                    // loop ends
+                    deps.retain(|x| !ancestors.contains(x));
+                    if deps.is_empty() {
+                        done.insert(e.id().clone());
+                        placements.insert(
+                            e.id(),
+                            Placement {
+                                position: ans.len(),
+                                pulled_by: ancestors[..ancestors.len() - 1].to_vec(),
+                            },
+                        );
+                        ans.push(e);
+                        lv.pop();
+                        ancestors.pop();
+                        if let Some(did) = lv.last() {
+                            ancestors.push(did.clone());
+                        }
+                        //eprintln!("made {}", eid);
+                    } else {
+                        deps.sort_by_cached_key(|a| hm.get(a).unwrap().priority());
+                        //eprintln!("to make a {}:{} i need {:?}", eid, e.priority(), deps);
+                        ancestors.push(deps.last().unwrap().clone());
+                        stack.push(deps);
+                    }
+                } else {
+                    lv.pop();
+                    ancestors.pop();
+                    if let Some(did) = lv.last() {
+                        ancestors.push(did.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    (ans, placements)
+}
+
+/// Loads all the enabled sources, merges them and puts the entries in the order they should be
+/// studied in
+pub fn process_entries(
+    registry: &SourceRegistry,
+    config: &Config,
+    args: &ProcessOptions,
+    report: &ErrorReport,
+) -> Result<Vec<CommonEntry>> {
+    let (hm, origins) = load_entries(registry, config, args, report)?;
+    let reader = Reader::new(&hm);
+    let mut graph = DependencyGraph::new(&hm, origins);
+    graph.break_cycles(&hm, args.cycle_policy);
+    let (mut ans, _) = order_entries(&hm, &graph, args);
+    if config.is_enabled(tatoeba::NAME) {
+        let path = config.path_or(tatoeba::NAME, tatoeba::DEFAULT_PATH.into());
+        tatoeba::add_examples(&mut ans, &path, report)?;
+    }
+    ans.par_iter_mut().for_each(|x| reader.fill(x));
+    Ok(ans)
+}
+
+/// Stores processed entries, so they can be reused with `read_entries`
+pub fn write_entries(path: &Path, entries: &[CommonEntry]) -> Result<()> {
+    let writer = std::io::BufWriter::new(create_file(path)?);
+    bincode::serialize_into(writer, entries).in_file(path)
+}
+pub fn read_entries(path: &Path) -> Result<Vec<CommonEntry>> {
+    let file = std::fs::File::open(path).in_file(path)?;
+    let reader = std::io::BufReader::new(file);
+    bincode::deserialize_from(reader).in_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Some characters, all with the traditional form `tag`
+    fn words(tag: &str) -> Vec<CommonEntry> {
+        ('一'..='丿')
+            .map(|c| {
+                let mut w = WordEntry::from_id(c.to_string());
+                w.traditional = Some(tag.into());
+                w.hsk_lev = Some(1);
+                w.into()
+            })
+            .collect()
+    }
+
+    fn traditional(hm: &HashMap<EntryId, CommonEntry>) -> HashSet<String> {
+        hm.values()
+            .map(|x| match x {
+                CommonEntry::WordEntry(w) => w.traditional.clone().unwrap_or_default(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge_keeps_precedence() {
+        let mut registry = SourceRegistry::empty();
+        registry.register(FnSource {
+            name: "a",
+            precedence: 20,
+            default_path: "",
+            load: |_, _| Ok(words("a")),
+        });
+        registry.register(FnSource {
+            name: "b",
+            precedence: 10,
+            default_path: "",
+            load: |_, _| Ok(words("b")),
+        });
+        let args = ProcessOptions::default();
+        let report = ErrorReport::default();
+        let (hm, _) = load_entries(&registry, &Config::default(), &args, &report).unwrap();
+        assert_eq!(hm.len(), words("").len());
+        assert_eq!(traditional(&hm), HashSet::from(["a".to_owned()]));
+
+        let config: Config = toml::from_str("[sources.b]\nprecedence = 30").unwrap();
+        let (hm, _) = load_entries(&registry, &config, &args, &report).unwrap();
+        assert_eq!(traditional(&hm), HashSet::from(["b".to_owned()]));
+    }
+}
@@ -0,0 +1,176 @@
+//! Readings of sentences no source gave the pinyin of, eg: examples.
+//! Sentences are cut in words with jieba and each word is read as the dictionary reads it, so
+//! polyphones get the reading of the word they are part of. Tones are the citation tones, the
+//! spoken ones are given by [`sandhi`].
+use crate::common::*;
+use crate::pinyin_type::*;
+use crate::utils::*;
+use std::collections::HashMap;
+
+pub struct Reader {
+    /// Main reading of each word, one syllable per character
+    words: HashMap<String, Vec<Syllable>>,
+}
+impl Reader {
+    /// Words with a guessed pinyin are left out, they would be read the same without them.
+    /// Erhua syllables cover two characters, eg: zhèr for 这儿
+    pub fn new(hm: &HashMap<EntryId, CommonEntry>) -> Self {
+        let words = hm
+            .values()
+            .filter_map(|e| match e {
+                CommonEntry::WordEntry(w) if !w.flags.contains(&Flag::GuessedPinyin) => {
+                    let syllables: Vec<Syllable> = w.pinyin.first()?.syllables().copied().collect();
+                    let chars: usize = syllables.iter().map(|x| x.written_chars()).sum();
+                    (chars == w.id.chars().count()).then(|| (w.id.clone(), syllables))
+                }
+                _ => None,
+            })
+            .collect();
+        Self { words }
+    }
+    /// Characters that are not in the dictionary are read with [`Pinyin::from_hanzi`], the
+    /// ones without reading (punctuation, latin letters...) are left out
+    pub fn read(&self, zh: &str) -> Pinyin {
+        let mut syllables = vec![];
+        for word in JIEBA.cut(zh, false) {
+            if let Some(s) = self.words.get(word) {
+                syllables.extend(s);
+                continue;
+            }
+            for c in word.chars() {
+                match self.words.get(&c.to_string()) {
+                    Some(s) => syllables.extend(s),
+                    None => syllables.extend(Pinyin::from_hanzi(c.to_string()).syllables()),
+                }
+            }
+        }
+        Pinyin::from_syllables(syllables)
+    }
+    /// Fills the readings of the triplets of the entry that have none
+    pub fn fill(&self, entry: &mut CommonEntry) {
+        let triplets: Vec<&mut Triplet> = match entry {
+            CommonEntry::WordEntry(w) => w.examples.iter_mut().collect(),
+            CommonEntry::GrammarEntry(g) => vec![&mut g.structure, &mut g.example],
+            CommonEntry::SyllableEntry(_) => vec![],
+        };
+        for t in triplets {
+            if t.py.is_empty() {
+                t.py = self.read(&t.zh);
+            }
+        }
+    }
+}
+
+fn is_number(c: char) -> bool {
+    "〇零一二三四五六七八九十百千万亿两第".contains(c)
+}
+
+/// Spoken tones of a reading with one syllable per character of `zh`:
+/// - 一 is read yí before a fourth tone and yì before the other tones, except in numbers
+/// - 不 is read bú before a fourth tone
+/// - a third tone before another third tone is read as a second tone
+///
+/// Punctuation between characters stops the rules from applying across it.
+/// Readings that do not line up with the characters are returned as is
+pub fn sandhi(zh: &str, py: &Pinyin) -> Pinyin {
+    // characters that have a reading, and whether punctuation comes before them
+    let mut chars: Vec<(char, bool)> = vec![];
+    let mut pause = false;
+    for c in zh.chars() {
+        if is_good_cjk(c) {
+            chars.push((c, pause));
+            pause = false;
+        } else if !c.is_whitespace() {
+            pause = true;
+        }
+    }
+    let syllables: Vec<&Syllable> = py.syllables().collect();
+    let erhua_written = syllables.iter().map(|x| x.written_chars()).sum::<usize>() == chars.len();
+    if !erhua_written && syllables.len() != chars.len() {
+        return py.clone();
+    }
+    // the 儿 of erhua syllables goes with the character before it
+    let mut written = chars.into_iter();
+    let chars: Vec<(char, bool)> = syllables
+        .iter()
+        .filter_map(|s| {
+            let c = written.next();
+            if erhua_written && s.erhua {
+                written.next();
+            }
+            c
+        })
+        .collect();
+    let citation: Vec<Tone> = syllables.iter().map(|x| x.tone).collect();
+    let mut tones = citation.clone();
+    for i in 0..chars.len() {
+        let prev = i.checked_sub(1).filter(|_| !chars[i].1).map(|j| chars[j].0);
+        let next = chars
+            .get(i + 1)
+            .filter(|x| !x.1)
+            .map(|x| (x.0, citation[i + 1]));
+        let Some((next_char, next_tone)) = next else {
+            continue;
+        };
+        tones[i] = match (chars[i].0, citation[i], next_tone) {
+            ('一', Tone::First, Tone::Neutral) => continue,
+            ('一', Tone::First, _) if prev.is_some_and(is_number) || is_number(next_char) => {
+                continue
+            }
+            ('一', Tone::First, Tone::Fourth) => Tone::Second,
+            ('一', Tone::First, _) => Tone::Fourth,
+            ('不', Tone::Fourth, Tone::Fourth) => Tone::Second,
+            (_, Tone::Third, Tone::Third) => Tone::Second,
+            _ => continue,
+        };
+    }
+    let mut tones = tones.into_iter();
+    Pinyin::from_parts(py.parts().iter().map(|x| match x {
+        PinyinPart::Syllable(s) => {
+            let mut s = *s;
+            s.tone = tones.next().unwrap_or(s.tone);
+            PinyinPart::Syllable(s)
+        }
+        PinyinPart::Other(_) => x.clone(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn read() {
+        let hm: HashMap<EntryId, CommonEntry> =
+            [("这儿", "zhe4 r5"), ("有", "you3"), ("人", "ren2")]
+                .into_iter()
+                .map(|(id, py)| {
+                    let mut w = WordEntry::from_id(id.into());
+                    w.pinyin = vec![Pinyin::from(py)];
+                    let e: CommonEntry = w.into();
+                    (e.id(), e)
+                })
+                .collect();
+        let reader = Reader::new(&hm);
+        assert_eq!(reader.read("这儿有人").to_string(), "zhèr yǒu rén");
+    }
+    #[test]
+    fn sandhi() {
+        for (zh, citation, spoken) in [
+            ("一个", "yi1 ge4", "yí gè"),
+            ("一天", "yi1 tian1", "yì tiān"),
+            ("第一天", "di4 yi1 tian1", "dì yī tiān"),
+            ("十一个", "shi2 yi1 ge4", "shí yī gè"),
+            ("不是", "bu4 shi4", "bú shì"),
+            ("不好", "bu4 hao3", "bù hǎo"),
+            ("你好", "ni3 hao3", "ní hǎo"),
+            ("我很好", "wo3 hen3 hao3", "wó hén hǎo"),
+            ("好，你", "hao3 ni3", "hǎo nǐ"),
+            ("一点儿", "yi1 dian3 r5", "yì diǎnr"),
+            ("有点儿", "you3 dian3 r5", "yóu diǎnr"),
+            ("好玩儿", "hao3 wan2 r5", "hǎo wánr"),
+        ] {
+            let py = super::sandhi(zh, &Pinyin::from(citation));
+            assert_eq!(py.to_string(), spoken, "{}", zh);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! How the priority of entries is computed.
+//! Entries are scored once after merging, by a [`Scorer`] chosen at build time, and keep their
+//! [`Score`] so it can be explained later.
+use crate::common::*;
+use crate::error::*;
+use ordered_float::NotNan;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A priority, with the parts it is made of
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Score {
+    pub parts: Vec<(String, NotNan<f32>)>,
+}
+impl Score {
+    pub fn total(&self) -> NotNan<f32> {
+        self.parts.iter().map(|x| x.1).sum()
+    }
+}
+
+/// Computes the priority of entries, higher priority means earlier in the deck.
+/// Implement it to plug a custom scoring in [`crate::process::ProcessOptions`]
+pub trait Scorer: std::fmt::Debug + Send + Sync {
+    fn word(&self, w: &WordEntry) -> Score;
+    fn grammar(&self, g: &GrammarEntry) -> Score;
+}
+
+/// Scores entries by adding their HSK level and their frequency, each with a weight.
+/// The builtin strategies are presets of it
+#[derive(Clone, Debug)]
+pub struct WeightedScorer {
+    pub hsk: f32,
+    pub frequency: f32,
+    /// Weight of the HSK level of grammar points
+    pub grammar: f32,
+    /// HSK level of words that are in no HSK list
+    pub missing_hsk_level: u8,
+    /// Multiplies the frequencies coming from each source, sources not listed weigh 1
+    pub sources: BTreeMap<String, f32>,
+    /// Multiplies the priority of words by their part of speech, eg: 0.5 for proper nouns to
+    /// demote them, or 0 to leave them out
+    pub pos: BTreeMap<PartOfSpeech, f32>,
+}
+
+pub const STRATEGIES: [&str; 4] = ["balanced", "exam", "reading", "conversation"];
+
+impl Default for WeightedScorer {
+    fn default() -> Self {
+        Self::strategy("balanced").unwrap()
+    }
+}
+impl WeightedScorer {
+    /// One of [`STRATEGIES`]:
+    /// - balanced: HSK level and frequency count the same
+    /// - exam: mostly HSK level, to prepare for the HSK exams
+    /// - reading: mostly frequency
+    /// - conversation: mostly frequency, favouring SUBTLEX which comes from film subtitles
+    pub fn strategy(name: &str) -> Result<Self> {
+        let (hsk, frequency, grammar, sources): (f32, f32, f32, &[(&str, f32)]) = match name {
+            "balanced" => (0.5, 0.5, 0.5, &[]),
+            "exam" => (0.8, 0.2, 0.7, &[]),
+            "reading" => (0.2, 0.8, 0.4, &[]),
+            "conversation" => (0.35, 0.65, 0.5, &[("subtlex", 1.5)]),
+            _ => {
+                return Err(Error::parse(format!(
+                    "unknown scoring strategy {}, expected one of {}",
+                    name,
+                    STRATEGIES.join(", ")
+                )))
+            }
+        };
+        Ok(Self {
+            hsk,
+            frequency,
+            grammar,
+            missing_hsk_level: 10,
+            sources: sources.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            pos: BTreeMap::new(),
+        })
+    }
+    fn source_weight(&self, source: &str) -> f32 {
+        self.sources.get(source).copied().unwrap_or(1f32)
+    }
+    /// 1 for HSK 1, 0 for HSK 10
+    fn hsk_level(&self, lev: Option<u8>) -> NotNan<f32> {
+        let lev = lev.unwrap_or(self.missing_hsk_level).min(10);
+        NotNan::new((10 - lev) as f32 / 10f32).unwrap()
+    }
+}
+impl Scorer for WeightedScorer {
+    fn word(&self, w: &WordEntry) -> Score {
+        let freq: NotNan<f32> = w
+            .freq
+            .iter()
+            .map(|x| x.value * self.source_weight(&x.origin.source))
+            .sum();
+        let hp = self.hsk_level(w.hsk_lev);
+        // words less frequent than 2^-16 all count as 0
+        let fp = (NotNan::new(freq.log2()).unwrap() + NotNan::new(16f32).unwrap())
+            .max(NotNan::new(0f32).unwrap())
+            / 16f32;
+
+        let mut score = Score {
+            parts: vec![
+                ("hsk".into(), hp * self.hsk),
+                ("frequency".into(), fp * self.frequency),
+            ],
+        };
+        if let Some(factor) = w.pos.and_then(|x| self.pos.get(&x)) {
+            let adjust = score.total() * (factor - 1f32);
+            score.parts.push(("part of speech".into(), adjust));
+        }
+        score
+    }
+    fn grammar(&self, g: &GrammarEntry) -> Score {
+        let hp = self.hsk_level(g.hsk_lev);
+        // earlier points of the level come first
+        let fp = 1f32 - g.hsk_sublev.unwrap_or(1f32);
+        Score {
+            parts: vec![
+                ("hsk".into(), hp * self.grammar),
+                ("position in level".into(), hp * fp * self.grammar * 0.5),
+            ],
+        }
+    }
+}
+
+/// Stores the score of the entry, syllables always have the same priority
+pub fn score_entry(scorer: &dyn Scorer, entry: &mut CommonEntry) {
+    match entry {
+        CommonEntry::WordEntry(w) => w.score = scorer.word(w),
+        CommonEntry::GrammarEntry(g) => g.score = scorer.grammar(g),
+        CommonEntry::SyllableEntry(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Priorities from before scoring was configurable
+    fn baseline_word(hsk_lev: Option<u8>, freq: &[f32]) -> f32 {
+        let freq: f32 = freq.iter().sum();
+        let hp = (10 - hsk_lev.unwrap_or(10)) as f32 / 10f32;
+        let fp = (freq.log2() + 16f32).max(0f32) / 16f32;
+        hp * 0.5 + fp * 0.5
+    }
+    fn baseline_grammar(hsk_lev: Option<u8>, hsk_sublev: Option<f32>) -> f32 {
+        let hp = (10 - hsk_lev.unwrap_or(10)) as f32 / 10f32;
+        let fp = 1f32 - hsk_sublev.unwrap_or(1f32);
+        hp * 0.5 + hp * fp * 0.25
+    }
+
+    #[test]
+    fn balanced_matches_baseline() {
+        let scorer = WeightedScorer::default();
+        for (hsk_lev, freq) in [
+            (Some(1), &[0.01f32][..]),
+            (Some(4), &[0.001, 0.0005][..]),
+            (None, &[1e-7][..]),
+            (None, &[][..]),
+        ] {
+            let mut w = WordEntry::from_id("字".into());
+            w.hsk_lev = hsk_lev;
+            w.freq = freq
+                .iter()
+                .map(|x| Frequency::from(NotNan::new(*x).unwrap()))
+                .collect();
+            let total = scorer.word(&w).total().into_inner();
+            assert!((total - baseline_word(hsk_lev, freq)).abs() < 1e-6);
+        }
+        let triplet = Triplet {
+            zh: String::new(),
+            en: String::new(),
+            py: Default::default(),
+            origin: Default::default(),
+        };
+        for (hsk_lev, hsk_sublev) in [(Some(1), Some(0f32)), (Some(3), Some(0.5)), (None, None)] {
+            let g = GrammarEntry {
+                id: "1".into(),
+                structure: triplet.clone(),
+                example: triplet.clone(),
+                hsk_lev,
+                hsk_sublev,
+                score: Default::default(),
+            };
+            let total = scorer.grammar(&g).total().into_inner();
+            assert!((total - baseline_grammar(hsk_lev, hsk_sublev)).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::common::*;
+use crate::error::*;
+use std::path::{Path, PathBuf};
+
+/// Something entries can be read from.
+/// Entries with the same id coming from different sources get merged,
+/// sources with a higher precedence are merged first, so for fields that can only hold one value
+/// (eg: traditional, audio_file) they win.
+pub trait DataSource: Send + Sync {
+    /// Unique name, used in the config and on the command line
+    fn name(&self) -> &str;
+    fn precedence(&self) -> i32;
+    /// Where the source is read from if the config does not say otherwise
+    fn default_path(&self) -> PathBuf;
+    /// Bad records are handled according to `report`
+    fn load(&self, path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>>;
+}
+
+/// A source backed by a loader function
+pub struct FnSource {
+    pub name: &'static str,
+    pub precedence: i32,
+    pub default_path: &'static str,
+    pub load: fn(&Path, &ErrorReport) -> Result<Vec<CommonEntry>>,
+}
+impl DataSource for FnSource {
+    fn name(&self) -> &str {
+        self.name
+    }
+    fn precedence(&self) -> i32 {
+        self.precedence
+    }
+    fn default_path(&self) -> PathBuf {
+        self.default_path.into()
+    }
+    fn load(&self, path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+        (self.load)(path, report)
+    }
+}
+
+pub fn builtin_sources() -> Vec<FnSource> {
+    use crate::*;
+    vec![
+        FnSource {
+            name: "hsk",
+            precedence: 120,
+            default_path: "res/HSK-3.0/HSK List (Meaning)",
+            load: hsk::get_hsks,
+        },
+        FnSource {
+            name: "unihan",
+            precedence: 110,
+            default_path: "res/unihan.csv",
+            load: unihan::get_records,
+        },
+        FnSource {
+            name: "dong",
+            precedence: 100,
+            default_path: "res/dictionary_char_2024-06-17.jsonl",
+            load: dong::get,
+        },
+        FnSource {
+            name: "allsetlearning",
+            precedence: 90,
+            default_path: "res/allsetlearning_grammar_keywords.json",
+            load: allsetlearning::get,
+        },
+        FnSource {
+            name: "anim-cjk-graphics",
+            precedence: 80,
+            default_path: "res/graphicsZhHans.txt",
+            load: anim_cjk::parse_graphics_zh_hans,
+        },
+        FnSource {
+            name: "anim-cjk-dictionary",
+            precedence: 70,
+            default_path: "res/dictionaryZhHans.txt",
+            load: anim_cjk::parse_dictionary_zh_hans,
+        },
+        FnSource {
+            name: "cedict",
+            precedence: 60,
+            default_path: "res/cedict_1_0_ts_utf-8_mdbg.txt",
+            load: cedict::get_cedict,
+        },
+        FnSource {
+            name: "subtlex",
+            precedence: 50,
+            default_path: "res/SUBTLEX-CH.txt",
+            load: freq::get_records,
+        },
+        FnSource {
+            name: "zh-cn-50k",
+            precedence: 40,
+            default_path: "res/zh_cn_50k.txt",
+            load: freq2::get_records,
+        },
+        FnSource {
+            name: "word-audio",
+            precedence: 30,
+            default_path: "res/audio-cmn/64k/hsk",
+            load: audio::get_word_audios,
+        },
+        FnSource {
+            name: "syllable-audio",
+            precedence: 20,
+            default_path: "res/audio2",
+            load: audio::get_syllable_audios,
+        },
+        FnSource {
+            name: "lp-grammar",
+            precedence: 10,
+            default_path: "res/lp_grammar.csv",
+            load: lp_grammar::get_records,
+        },
+    ]
+}
+
+/// The sources entries are merged from
+pub struct SourceRegistry {
+    sources: Vec<Box<dyn DataSource>>,
+}
+impl Default for SourceRegistry {
+    /// A registry with all the builtin sources
+    fn default() -> Self {
+        let mut r = Self::empty();
+        for s in builtin_sources() {
+            r.register(s);
+        }
+        r
+    }
+}
+impl SourceRegistry {
+    pub fn empty() -> Self {
+        Self { sources: vec![] }
+    }
+    /// Panics if a source with the same name was already registered
+    pub fn register<S: DataSource + 'static>(&mut self, source: S) {
+        assert!(
+            self.get(source.name()).is_none(),
+            "source {} registered twice",
+            source.name()
+        );
+        self.sources.push(Box::new(source));
+    }
+    pub fn get(&self, name: &str) -> Option<&dyn DataSource> {
+        self.sources
+            .iter()
+            .find(|s| s.name() == name)
+            .map(|s| s.as_ref())
+    }
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.sources.iter().map(|s| s.name())
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &dyn DataSource> {
+        self.sources.iter().map(|s| s.as_ref())
+    }
+}
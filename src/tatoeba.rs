@@ -1,9 +1,15 @@
 use crate::common::*;
+use crate::error::*;
 use crate::pinyin_type::*;
 use crate::utils::*;
 use itertools::Itertools;
 use ordered_float::NotNan;
+use rayon::prelude::*;
 use serde::Deserialize;
+use std::path::Path;
+
+pub const NAME: &str = "tatoeba";
+pub const DEFAULT_PATH: &str = "res/tatoeba-zh-en.tsv";
 
 #[derive(Deserialize)]
 struct TatoebaRecord {
@@ -22,11 +28,11 @@ struct Example {
 }
 impl From<Example> for Triplet {
     fn from(e: Example) -> Self {
-        let epy = Pinyin::from_hanzi(&e.zh);
         Self {
             zh: e.zh,
             en: e.en,
-            py: epy,
+            py: Pinyin::default(),
+            origin: Provenance::source(NAME),
         }
     }
 }
@@ -50,25 +56,28 @@ impl From<TatoebaRecord> for Example {
     }
 }
 
-fn get_records() -> Vec<Example> {
-    let file = std::fs::File::open("res/tatoeba-zh-en.tsv").unwrap();
+fn get_records(path: &Path, report: &ErrorReport) -> Result<Vec<Example>> {
+    let file = std::fs::File::open(path).in_file(path)?;
     let reader = std::io::BufReader::new(file);
     let mut rdr = csv::ReaderBuilder::new()
         .has_headers(false)
         .delimiter(b'\t')
         .quoting(false)
         .from_reader(reader);
-    let records: Vec<TatoebaRecord> = rdr
-        .deserialize::<TatoebaRecord>()
-        .map(|r| r.unwrap())
-        .collect();
-    records
+    let mut records: Vec<TatoebaRecord> = vec![];
+    for r in rdr.deserialize::<TatoebaRecord>() {
+        if let Some(r) = report.check(r.in_file(path))? {
+            records.push(r);
+        }
+    }
+    let records: Vec<TatoebaRecord> = records
         .into_iter()
         .rev()
         .unique_by(|x| x.zh_id)
         .filter(|x| x.zh.chars().count() < 27 && x.en.chars().count() < 61)
-        .map(Example::from)
-        .collect()
+        .collect();
+    // tokenizing is the slow part
+    Ok(records.into_par_iter().map(Example::from).collect())
 }
 
 fn length_bonus(s: &str) -> NotNan<f32> {
@@ -86,9 +95,9 @@ fn length_bonus(s: &str) -> NotNan<f32> {
 }
 
 use std::collections::HashMap;
-pub fn add_examples(v: &mut [CommonEntry]) {
+pub fn add_examples(v: &mut [CommonEntry], path: &Path, report: &ErrorReport) -> Result<()> {
     // build tatoeba records trie
-    let records = get_records();
+    let records = get_records(path, report)?;
     let mut trie = ptrie::Trie::new();
     for (i, record) in records.iter().enumerate() {
         for (st, _) in record.zh.char_indices() {
@@ -121,47 +130,55 @@ pub fn add_examples(v: &mut [CommonEntry]) {
         .collect();
     maxpr.reverse();
 
-    let it = v.iter_mut().filter_map(|x| match x {
-        CommonEntry::WordEntry(inner) => Some(inner),
-        _ => None,
-    });
-
-    for (thisord, (i, thispr)) in it.zip(maxpr.into_iter()).enumerate() {
-        i.examples.extend(
-            trie.find_postfixes(i.id.bytes())
-                .into_iter()
-                .unique()
-                .map(|x| &records[*x])
-                .k_largest_by_key(3, |x| -> NotNan<f32> {
-                    let lb = length_bonus(&x.zh);
-                    let cb = NotNan::new(if x.tokens.contains(&i.id) {
-                        1.0f32
-                    } else {
-                        0.0f32
-                    })
-                    .unwrap();
+    let mut words: Vec<&mut WordEntry> = v
+        .iter_mut()
+        .filter_map(|x| match x {
+            CommonEntry::WordEntry(inner) => Some(inner),
+            _ => None,
+        })
+        .collect();
 
-                    let um: NotNan<f32> = x
-                        .tokens
-                        .iter()
-                        .map(|x| {
-                            hm.get(x)
-                                .cloned()
-                                .unwrap_or((usize::MAX, NotNan::new(0f32).unwrap()))
+    words
+        .par_iter_mut()
+        .zip(maxpr.into_par_iter())
+        .enumerate()
+        .for_each(|(thisord, (i, thispr))| {
+            i.examples.extend(
+                trie.find_postfixes(i.id.bytes())
+                    .into_iter()
+                    .unique()
+                    .map(|x| &records[*x])
+                    .k_largest_by_key(3, |x| -> NotNan<f32> {
+                        let lb = length_bonus(&x.zh);
+                        let cb = NotNan::new(if x.tokens.contains(&i.id) {
+                            1.0f32
+                        } else {
+                            0.0f32
                         })
-                        .map(|(ord, pr)| {
-                            if ord <= thisord || pr > thispr {
-                                NotNan::new(0.2f32).unwrap()
-                            } else {
-                                (pr - thispr) * NotNan::new(4f32).unwrap()
-                            }
-                        })
-                        .sum();
+                        .unwrap();
 
-                    NotNan::new(3f32).unwrap() * lb + NotNan::new(5f32).unwrap() * cb + um
-                })
-                .cloned()
-                .map(Triplet::from),
-        );
-    }
+                        let um: NotNan<f32> = x
+                            .tokens
+                            .iter()
+                            .map(|x| {
+                                hm.get(x)
+                                    .cloned()
+                                    .unwrap_or((usize::MAX, NotNan::new(0f32).unwrap()))
+                            })
+                            .map(|(ord, pr)| {
+                                if ord <= thisord || pr > thispr {
+                                    NotNan::new(0.2f32).unwrap()
+                                } else {
+                                    (pr - thispr) * NotNan::new(4f32).unwrap()
+                                }
+                            })
+                            .sum();
+
+                        NotNan::new(3f32).unwrap() * lb + NotNan::new(5f32).unwrap() * cb + um
+                    })
+                    .cloned()
+                    .map(Triplet::from),
+            );
+        });
+    Ok(())
 }
@@ -1,6 +1,9 @@
 use crate::common::*;
+use crate::error::*;
 use crate::pinyin_type::*;
+use crate::utils::read_csv;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -30,6 +33,7 @@ impl From<Record> for WordEntry {
                     .split(";")
                     .map(|x| x.trim().to_owned())
                     .collect(),
+                origin: Provenance::default(),
             }];
             let fd = w.definitions[0].english[0].clone();
             w.simple_definitions = vec![fd.split_once(',').map(|x| x.0.to_owned()).unwrap_or(fd)];
@@ -39,15 +43,16 @@ impl From<Record> for WordEntry {
     }
 }
 
-pub fn get_records() -> impl Iterator<Item = CommonEntry> {
-    let file = std::fs::File::open("res/unihan.csv").unwrap();
+pub fn get_records(path: &Path, report: &ErrorReport) -> Result<Vec<CommonEntry>> {
+    let file = std::fs::File::open(path).in_file(path)?;
     let reader = std::io::BufReader::new(file);
-    let mut rdr = csv::ReaderBuilder::new().from_reader(reader);
-    let v: Vec<CommonEntry> = rdr
-        .deserialize::<Record>()
-        .map(|r| r.unwrap())
-        .map(WordEntry::from)
-        .map(CommonEntry::from)
-        .collect();
-    v.into_iter()
+    let rdr = csv::ReaderBuilder::new().from_reader(reader);
+    Ok(read_csv::<Record, _>(rdr, path, report)?
+        .into_iter()
+        .map(|(line, r)| {
+            let mut w = WordEntry::from(r);
+            w.stamp(&Provenance::line(line));
+            w.into()
+        })
+        .collect())
 }
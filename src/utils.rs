@@ -1,3 +1,4 @@
+use crate::error::*;
 use core::hash::{Hash, Hasher};
 use siphasher::sip::SipHasher;
 use std::collections::HashSet;
@@ -109,3 +110,55 @@ pub fn guid_for<T: Hash>(v: T) -> String {
     let u: u64 = h.finish();
     base91_encode(u)
 }
+
+/// Creates the file and the directories leading to it
+pub fn create_file(path: &std::path::Path) -> Result<std::fs::File> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).in_file(dir)?;
+    }
+    std::fs::File::create(path).in_file(path)
+}
+
+/// Reads the records of a csv file with the line each starts on, bad records are handled
+/// according to `report`
+pub fn read_csv<T: serde::de::DeserializeOwned, R: std::io::Read>(
+    mut rdr: csv::Reader<R>,
+    path: &std::path::Path,
+    report: &ErrorReport,
+) -> Result<Vec<(u64, T)>> {
+    let headers = if rdr.has_headers() {
+        Some(rdr.headers().in_file(path)?.clone())
+    } else {
+        None
+    };
+    let mut v = vec![];
+    for r in rdr.records() {
+        let Some(record) = report.check(r.in_file(path))? else {
+            continue;
+        };
+        let line = record.position().map(|x| x.line()).unwrap_or_default();
+        let r = record.deserialize(headers.as_ref()).at(path, line);
+        if let Some(x) = report.check(r)? {
+            v.push((line, x));
+        }
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn csv_lines() {
+        let data = "word\tcount\n好\t3\n你\tx\n人\t1\n";
+        let rdr = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_reader(data.as_bytes());
+        let report = ErrorReport::new(OnError::Skip);
+        let path = std::path::Path::new("counts.tsv");
+        let v: Vec<(u64, (String, u32))> = read_csv(rdr, path, &report).unwrap();
+        let lines: Vec<_> = v.iter().map(|(line, (w, _))| (*line, w.as_str())).collect();
+        assert_eq!(lines, vec![(2, "好"), (4, "人")]);
+        assert_eq!(report.skipped()[0].line, Some(3));
+    }
+}